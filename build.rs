@@ -4,5 +4,5 @@ mod generate_ast;
 use generate_ast::*;
 
 fn main() -> io::Result<()> {
-    generate_ast(&"src".to_string())
+    generate_ast("src")
 }
\ No newline at end of file
@@ -12,20 +12,24 @@ pub fn generate_ast(output_dir: &str) -> io::Result<()> {
     define_ast(
         output_dir,
         "Expr",
-        &["errors", "tokens", "rc"],
+        &["enviroment", "errors", "tokens", "cell", "rc"],
         &[
-            "Assign   : Token name, Rc<Expr> value",
+            "Assign   : Token name, Rc<Expr> value, Cell<Option<Slot>> slot",
             "Binary   : Rc<Expr> left, Token operator, Rc<Expr> right",
-            "Call     : Rc<Expr> callee, Token paren, Vec<Rc<Expr>> arguments",
+            "Call     : Rc<Expr> callee, Token paren, Vec<Rc<Expr>> arguments, Vec<Token> keywords, Vec<Rc<Expr>> keyword_values, Cell<bool> tail",
             "Get      : Rc<Expr> object, Token name",
             "Grouping : Rc<Expr> expression",
+            "Index    : Rc<Expr> object, Token bracket, Rc<Expr> index",
+            "List     : Token bracket, Vec<Rc<Expr>> elements",
             "Literal  : Option<Object> value",
             "Logical  : Rc<Expr> left, Token operator, Rc<Expr> right",
+            "Map      : Token brace, Vec<Rc<Expr>> keys, Vec<Rc<Expr>> values",
             "Set      : Rc<Expr> object, Token name, Rc<Expr> value",
-            "Super    : Token keyword, Token method",
-            "This     : Token keyword",
+            "SetIndex : Rc<Expr> object, Token bracket, Rc<Expr> index, Rc<Expr> value",
+            "Super    : Token keyword, Token method, Cell<Option<Slot>> slot",
+            "This     : Token keyword, Cell<Option<Slot>> slot",
             "Unary    : Token operator, Rc<Expr> right",
-            "Variable : Token name",
+            "Variable : Token name, Cell<Option<Slot>> slot",
         ],
     )?;
     define_ast(
@@ -34,12 +38,14 @@ pub fn generate_ast(output_dir: &str) -> io::Result<()> {
         &["errors", "expr", "tokens", "rc"],
         &[
             "Block      : Rc<Vec<Rc<Stmt>>> statements",
-            "Class      : Token name, Option<Rc<Expr>> superclass, Rc<Vec<Rc<Stmt>>> methods",
+            "Class      : Token name, Option<Rc<Expr>> superclass, Rc<Vec<Rc<Expr>>> traits, Rc<Vec<Rc<Expr>>> interfaces, Rc<Vec<Rc<Stmt>>> fields, Rc<Vec<Rc<Stmt>>> methods, Rc<Vec<Rc<Stmt>>> getters, Rc<Vec<Rc<Stmt>>> setters, Rc<Vec<Rc<Stmt>>> abstract_methods",
             "Expression : Rc<Expr> expression",
             "Function   : Token name, Rc<Vec<Token>> params, Rc<Vec<Rc<Stmt>>> body",
             "If         : Rc<Expr> condition, Rc<Stmt> then_branch, Option<Rc<Stmt>> else_branch",
+            "Interface  : Token name, Rc<Vec<Rc<Stmt>>> methods",
             "Print      : Rc<Expr> expression",
             "Return     : Token keyword, Option<Rc<Expr>> value",
+            "Trait      : Token name, Rc<Vec<Rc<Stmt>>> methods, Rc<Vec<Rc<Stmt>>> required",
             "Var        : Token name, Option<Rc<Expr>> initializer",
             "While      : Rc<Expr> condition, Rc<Stmt> body",
         ],
@@ -60,6 +66,8 @@ fn define_ast(
     for i in imports {
         if i == &"rc" {
             writeln!(file, "use std::rc::Rc;")?;
+        } else if i == &"cell" {
+            writeln!(file, "use std::cell::Cell;")?;
         } else {
             writeln!(file, "use crate::{}::*;", i)?;
         }
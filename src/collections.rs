@@ -0,0 +1,201 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::reflection::type_name;
+use crate::tokens::*;
+use std::cmp::Ordering;
+
+// Callbacks run arc code that may change the list being walked, so each
+// native works on a copy of the elements rather than holding a borrow.
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("map", 2, native_map),
+        NativeFunction::new("filter", 2, native_filter),
+        NativeFunction::variadic("reduce", 2, native_reduce),
+        NativeFunction::variadic("any", 1, native_any),
+        NativeFunction::variadic("all", 1, native_all),
+        NativeFunction::variadic("zip", 1, native_zip),
+        NativeFunction::new("enumerate", 1, native_enumerate),
+        NativeFunction::new("sort", 1, native_sort).with_keywords(&["key", "compare"]),
+    ]
+}
+
+fn elements(value: &Object, native: &str) -> Result<Vec<Object>, Error> {
+    match value {
+        Object::List(list) => Ok(list.borrow().clone()),
+        _ => Err(Error::call_error(&format!("{native}() expects a list."))),
+    }
+}
+
+fn list(elements: Vec<Object>) -> Object {
+    Object::list(elements)
+}
+
+fn native_map(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let mut results = Vec::new();
+    for element in elements(&args[0], "map")? {
+        results.push(terp.call(&args[1], vec![element], Vec::new())?);
+    }
+    Ok(list(results))
+}
+
+fn native_filter(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let mut kept = Vec::new();
+    for element in elements(&args[0], "filter")? {
+        if terp.is_truthy(terp.call(&args[1], vec![element.clone()], Vec::new())?) {
+            kept.push(element);
+        }
+    }
+    Ok(list(kept))
+}
+
+// reduce(list, fn, initial). Without `initial`, the first element is used.
+fn native_reduce(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let mut elements = elements(&args[0], "reduce")?.into_iter();
+    let mut accumulator = match args {
+        [_, _] => elements
+            .next()
+            .ok_or_else(|| Error::call_error("reduce() of an empty list with no initial value."))?,
+        [_, _, initial] => initial.clone(),
+        _ => {
+            return Err(Error::call_error(&format!(
+                "Expected at most 3 arguments but got {}.",
+                args.len()
+            )))
+        }
+    };
+    for element in elements {
+        accumulator = terp.call(&args[1], vec![accumulator, element], Vec::new())?;
+    }
+    Ok(accumulator)
+}
+
+// any(list, fn) and all(list, fn) test each element with `fn`, or for
+// truthiness when it is left out. Both stop at the first deciding element.
+fn test(terp: &Interpreter, args: &[Object], native: &str, wanted: bool) -> Result<Object, Error> {
+    if args.len() > 2 {
+        return Err(Error::call_error(&format!(
+            "Expected at most 2 arguments but got {}.",
+            args.len()
+        )));
+    }
+    for element in elements(&args[0], native)? {
+        let result = match args.get(1) {
+            Some(predicate) => terp.call(predicate, vec![element], Vec::new())?,
+            None => element,
+        };
+        if terp.is_truthy(result) == wanted {
+            return Ok(Object::Bool(wanted));
+        }
+    }
+    Ok(Object::Bool(!wanted))
+}
+
+fn native_any(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    test(terp, args, "any", true)
+}
+
+fn native_all(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    test(terp, args, "all", false)
+}
+
+// The result is as long as the shortest list.
+fn native_zip(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let lists = args
+        .iter()
+        .map(|arg| elements(arg, "zip"))
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    let tuples = (0..len)
+        .map(|i| list(lists.iter().map(|l| l[i].clone()).collect()))
+        .collect();
+    Ok(list(tuples))
+}
+
+fn native_enumerate(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let pairs = elements(&args[0], "enumerate")?
+        .into_iter()
+        .enumerate()
+        .map(|(i, element)| list(vec![Object::Num(i as f64), element]))
+        .collect();
+    Ok(list(pairs))
+}
+
+/// How `sort` orders two values: by calling the comparator, or by the
+/// natural order of numbers and of strings.
+struct Ordered<'a> {
+    interpreter: &'a Interpreter,
+    compare: &'a Object,
+}
+
+impl Ordered<'_> {
+    fn cmp(&self, a: &Object, b: &Object) -> Result<Ordering, Error> {
+        if *self.compare != Object::Nil {
+            return match self.interpreter.call(self.compare, vec![a.clone(), b.clone()], Vec::new())? {
+                Object::Num(n) if n < 0.0 => Ok(Ordering::Less),
+                Object::Num(n) if n > 0.0 => Ok(Ordering::Greater),
+                Object::Num(0.0) => Ok(Ordering::Equal),
+                _ => Err(Error::call_error("sort() comparator must return a number.")),
+            };
+        }
+        let ordering = match (a, b) {
+            (Object::Num(a), Object::Num(b)) => a.partial_cmp(b),
+            (Object::Str(a), Object::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        };
+        ordering.ok_or_else(|| {
+            Error::call_error(&format!(
+                "sort() cannot compare {} with {}.",
+                type_name(a),
+                type_name(b)
+            ))
+        })
+    }
+
+    // A merge sort, which is stable and lets comparator errors propagate.
+    fn sort(&self, items: Vec<(Object, Object)>) -> Result<Vec<(Object, Object)>, Error> {
+        if items.len() <= 1 {
+            return Ok(items);
+        }
+        let mut left = items;
+        let right = left.split_off(left.len() / 2);
+        let left = self.sort(left)?;
+        let right = self.sort(right)?;
+
+        let mut merged = Vec::with_capacity(left.len() + right.len());
+        let mut left = left.into_iter().peekable();
+        let mut right = right.into_iter().peekable();
+        while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+            if self.cmp(&r.0, &l.0)? == Ordering::Less {
+                merged.push(right.next().unwrap());
+            } else {
+                merged.push(left.next().unwrap());
+            }
+        }
+        merged.extend(left);
+        merged.extend(right);
+        Ok(merged)
+    }
+}
+
+// sort(list, key: fn, compare: fn) returns a sorted copy. `key` maps each
+// element to the value it is sorted by; `compare(a, b)` returns a negative
+// number, zero or a positive number.
+fn native_sort(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (key, compare) = (&args[1], &args[2]);
+    let mut items = Vec::new();
+    for element in elements(&args[0], "sort")? {
+        let sort_key = match key {
+            Object::Nil => element.clone(),
+            key => terp.call(key, vec![element.clone()], Vec::new())?,
+        };
+        items.push((sort_key, element));
+    }
+    let ordered = Ordered {
+        interpreter: terp,
+        compare,
+    };
+    let sorted = ordered.sort(items)?;
+    Ok(list(sorted.into_iter().map(|(_, element)| element).collect()))
+}
@@ -0,0 +1,668 @@
+use std::cell::{Cell, RefCell, RefMut};
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::errors::*;
+use crate::expr::*;
+use crate::interner::{intern, RcStr};
+use crate::stmt::*;
+use crate::tokens::*;
+use crate::vm::*;
+
+/// Compiles a resolved program to bytecode for the VM. Locals live in
+/// stack slots, and variables captured by closures become upvalues.
+pub struct Compiler {
+    functions: RefCell<Vec<FunctionState>>,
+    had_error: Cell<bool>,
+}
+
+#[derive(PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Initializer,
+    Method,
+}
+
+/// A function being compiled.
+struct FunctionState {
+    name: Token,
+    params: Rc<Vec<Token>>,
+    function_type: FunctionType,
+    chunk: Chunk,
+    locals: Vec<Local>,
+    captures: Vec<Capture>,
+    scope_depth: usize,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    captured: bool,
+}
+
+enum Variable {
+    Local(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+impl StmtVisitor<()> for Compiler {
+    fn visit_class_stmt(&self, _: Rc<Stmt>, stmt: &ClassStmt) -> Result<(), Error> {
+        self.emit(Op::Nil);
+        self.define_variable(&stmt.name);
+
+        // The superclass stays in a local named `super`, for methods to
+        // capture.
+        if let Some(superclass) = &stmt.superclass {
+            self.begin_scope();
+            self.compile_expr(superclass.clone());
+            self.expect(Kind::Class, superclass);
+            self.add_local("super");
+            self.compile_expr(superclass.clone());
+        }
+        for t in stmt.traits.iter() {
+            self.compile_expr(t.clone());
+            self.expect(Kind::Trait, t);
+        }
+        for interface in stmt.interfaces.iter() {
+            self.compile_expr(interface.clone());
+            self.expect(Kind::Interface, interface);
+        }
+
+        // Field initializers run like a method without parameters.
+        if !stmt.fields.is_empty() {
+            let declaration = FunctionStmt {
+                name: stmt.name.clone(),
+                params: Rc::new(Vec::new()),
+                body: Rc::clone(&stmt.fields),
+            };
+            self.function(&declaration, FunctionType::Method);
+        }
+        let methods = self.methods(&stmt.methods, true);
+        let getters = self.methods(&stmt.getters, false);
+        let setters = self.methods(&stmt.setters, false);
+
+        let layout = Layout {
+            name: stmt.name.clone(),
+            superclass: stmt.superclass.is_some(),
+            traits: stmt.traits.len(),
+            interfaces: stmt.interfaces.len(),
+            fields: stmt
+                .fields
+                .iter()
+                .filter_map(|field| match field.deref() {
+                    Stmt::Expression(e) => match e.expression.deref() {
+                        Expr::Set(set) => Some(set.name.lexeme.to_string()),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect(),
+            field_initializer: !stmt.fields.is_empty(),
+            methods,
+            getters,
+            setters,
+            signatures: Self::signatures(&stmt.abstract_methods),
+        };
+        let index = self.add_layout(layout);
+        self.emit(Op::Class(index));
+        self.set_variable(&stmt.name);
+        self.emit(Op::Pop);
+
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+        Ok(())
+    }
+
+    fn visit_trait_stmt(&self, _: Rc<Stmt>, stmt: &TraitStmt) -> Result<(), Error> {
+        let layout = Layout {
+            name: stmt.name.clone(),
+            methods: self.methods(&stmt.methods, false),
+            signatures: Self::signatures(&stmt.required),
+            ..Layout::default()
+        };
+        let index = self.add_layout(layout);
+        self.emit(Op::Trait(index));
+        self.define_variable(&stmt.name);
+        Ok(())
+    }
+
+    fn visit_interface_stmt(&self, _: Rc<Stmt>, stmt: &InterfaceStmt) -> Result<(), Error> {
+        let layout = Layout {
+            name: stmt.name.clone(),
+            signatures: Self::signatures(&stmt.methods),
+            ..Layout::default()
+        };
+        let index = self.add_layout(layout);
+        self.emit(Op::Interface(index));
+        self.define_variable(&stmt.name);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&self, _: Rc<Stmt>, stmt: &ReturnStmt) -> Result<(), Error> {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit(Op::GetLocal(0));
+        } else if let Some(value) = stmt.value.clone() {
+            self.compile_expr(value);
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+        Ok(())
+    }
+
+    // A local function is declared before its body is compiled, so it can
+    // call itself.
+    fn visit_function_stmt(&self, _: Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), Error> {
+        if self.current().scope_depth > 0 {
+            self.add_local(&stmt.name.lexeme);
+            self.function(stmt, FunctionType::Function);
+        } else {
+            self.function(stmt, FunctionType::Function);
+            let name = self.add_token(&stmt.name);
+            self.emit(Op::DefineGlobal(name));
+        }
+        Ok(())
+    }
+
+    fn visit_if_stmt(&self, _: Rc<Stmt>, stmt: &IfStmt) -> Result<(), Error> {
+        self.compile_expr(stmt.condition.clone());
+        let then_jump = self.emit(Op::PopJumpIfFalse(0));
+        self.compile_stmt(stmt.then_branch.clone());
+        if let Some(else_branch) = stmt.else_branch.clone() {
+            let else_jump = self.emit(Op::Jump(0));
+            self.patch_jump(then_jump);
+            self.compile_stmt(else_branch);
+            self.patch_jump(else_jump);
+        } else {
+            self.patch_jump(then_jump);
+        }
+        Ok(())
+    }
+
+    fn visit_block_stmt(&self, _: Rc<Stmt>, stmt: &BlockStmt) -> Result<(), Error> {
+        self.begin_scope();
+        self.compile(&stmt.statements);
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_print_stmt(&self, _: Rc<Stmt>, stmt: &PrintStmt) -> Result<(), Error> {
+        self.compile_expr(stmt.expression.clone());
+        self.emit(Op::Print);
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&self, _: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), Error> {
+        self.compile_expr(stmt.expression.clone());
+        self.emit(Op::Pop);
+        Ok(())
+    }
+
+    fn visit_var_stmt(&self, _: Rc<Stmt>, stmt: &VarStmt) -> Result<(), Error> {
+        if let Some(initializer) = stmt.initializer.clone() {
+            self.compile_expr(initializer);
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.define_variable(&stmt.name);
+        Ok(())
+    }
+
+    fn visit_while_stmt(&self, _: Rc<Stmt>, stmt: &WhileStmt) -> Result<(), Error> {
+        let start = self.current().chunk.code.len() as u32;
+        self.compile_expr(stmt.condition.clone());
+        let exit_jump = self.emit(Op::PopJumpIfFalse(0));
+        self.compile_stmt(stmt.body.clone());
+        self.emit(Op::Jump(start));
+        self.patch_jump(exit_jump);
+        Ok(())
+    }
+}
+
+impl ExprVisitor<()> for Compiler {
+    fn visit_super_expr(&self, _: Rc<Expr>, expr: &SuperExpr) -> Result<(), Error> {
+        self.get_variable(&Token::new(TokenKind::This, intern("this"), None, expr.keyword.line));
+        self.get_variable(&expr.keyword);
+        let method = self.add_token(&expr.method);
+        self.emit(Op::GetSuper(method));
+        Ok(())
+    }
+
+    fn visit_this_expr(&self, _: Rc<Expr>, expr: &ThisExpr) -> Result<(), Error> {
+        self.get_variable(&expr.keyword);
+        Ok(())
+    }
+
+    fn visit_set_expr(&self, _: Rc<Expr>, expr: &SetExpr) -> Result<(), Error> {
+        self.compile_expr(expr.object.clone());
+        self.compile_expr(expr.value.clone());
+        let name = self.add_token(&expr.name);
+        self.emit(Op::SetProperty(name));
+        Ok(())
+    }
+
+    fn visit_get_expr(&self, _: Rc<Expr>, expr: &GetExpr) -> Result<(), Error> {
+        self.compile_expr(expr.object.clone());
+        let name = self.add_token(&expr.name);
+        self.emit(Op::GetProperty(name));
+        Ok(())
+    }
+
+    fn visit_list_expr(&self, _: Rc<Expr>, expr: &ListExpr) -> Result<(), Error> {
+        for element in expr.elements.iter() {
+            self.compile_expr(element.clone());
+        }
+        self.emit(Op::List(expr.elements.len() as u32));
+        Ok(())
+    }
+
+    fn visit_map_expr(&self, _: Rc<Expr>, expr: &MapExpr) -> Result<(), Error> {
+        let Ok(count) = u16::try_from(expr.keys.len()) else {
+            self.error(&expr.brace, "Too many entries in map literal.");
+            return Ok(());
+        };
+        for (key, value) in expr.keys.iter().zip(expr.values.iter()) {
+            self.compile_expr(key.clone());
+            self.compile_expr(value.clone());
+        }
+        let brace = self.add_token(&expr.brace);
+        self.emit(Op::Map(count, brace));
+        Ok(())
+    }
+
+    fn visit_index_expr(&self, _: Rc<Expr>, expr: &IndexExpr) -> Result<(), Error> {
+        self.compile_expr(expr.object.clone());
+        self.compile_expr(expr.index.clone());
+        let bracket = self.add_token(&expr.bracket);
+        self.emit(Op::GetIndex(bracket));
+        Ok(())
+    }
+
+    fn visit_setindex_expr(&self, _: Rc<Expr>, expr: &SetIndexExpr) -> Result<(), Error> {
+        self.compile_expr(expr.object.clone());
+        self.compile_expr(expr.index.clone());
+        self.compile_expr(expr.value.clone());
+        let bracket = self.add_token(&expr.bracket);
+        self.emit(Op::SetIndex(bracket));
+        Ok(())
+    }
+
+    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<(), Error> {
+        self.compile_expr(expr.callee.clone());
+        for argument in expr.arguments.iter().chain(expr.keyword_values.iter()) {
+            self.compile_expr(argument.clone());
+        }
+
+        // The parser allows at most 255 arguments.
+        let count = expr.arguments.len() as u8;
+        if expr.keywords.is_empty() {
+            let paren = self.add_token(&expr.paren);
+            if expr.tail.get() {
+                self.emit(Op::TailCall(count, paren));
+            } else {
+                self.emit(Op::Call(count, paren));
+            }
+        } else {
+            let mut function = self.current();
+            function.chunk.keyword_calls.push((expr.keywords.clone(), expr.paren.clone()));
+            let call = function.chunk.keyword_calls.len() as u32 - 1;
+            drop(function);
+            self.emit(Op::CallKeywords(count, call));
+        }
+        Ok(())
+    }
+
+    fn visit_logical_expr(&self, _: Rc<Expr>, expr: &LogicalExpr) -> Result<(), Error> {
+        self.compile_expr(expr.left.clone());
+        let jump = if expr.operator.kind == TokenKind::Or {
+            self.emit(Op::JumpIfTrue(0))
+        } else {
+            self.emit(Op::JumpIfFalse(0))
+        };
+        self.emit(Op::Pop);
+        self.compile_expr(expr.right.clone());
+        self.patch_jump(jump);
+        Ok(())
+    }
+
+    fn visit_assign_expr(&self, _: Rc<Expr>, expr: &AssignExpr) -> Result<(), Error> {
+        self.compile_expr(expr.value.clone());
+        self.set_variable(&expr.name);
+        Ok(())
+    }
+
+    fn visit_literal_expr(&self, _: Rc<Expr>, expr: &LiteralExpr) -> Result<(), Error> {
+        match expr.value.clone() {
+            Some(Object::Nil) | None => self.emit(Op::Nil),
+            Some(Object::Bool(true)) => self.emit(Op::True),
+            Some(Object::Bool(false)) => self.emit(Op::False),
+            Some(value) => {
+                let mut function = self.current();
+                function.chunk.constants.push(value);
+                let index = function.chunk.constants.len() as u32 - 1;
+                drop(function);
+                self.emit(Op::Constant(index))
+            }
+        };
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&self, _: Rc<Expr>, expr: &GroupingExpr) -> Result<(), Error> {
+        self.compile_expr(expr.expression.clone());
+        Ok(())
+    }
+
+    fn visit_unary_expr(&self, _: Rc<Expr>, expr: &UnaryExpr) -> Result<(), Error> {
+        self.compile_expr(expr.right.clone());
+        match expr.operator.kind {
+            TokenKind::Minus => {
+                self.emit(Op::Negate);
+            }
+            TokenKind::Bang => {
+                self.emit(Op::Not);
+            }
+            _ => self.error(&expr.operator, "Invalid unary operator"),
+        }
+        Ok(())
+    }
+
+    fn visit_binary_expr(&self, _: Rc<Expr>, expr: &BinaryExpr) -> Result<(), Error> {
+        self.compile_expr(expr.left.clone());
+        self.compile_expr(expr.right.clone());
+        let operator = self.add_token(&expr.operator);
+        let op = match expr.operator.kind {
+            TokenKind::Plus => Op::Add(operator),
+            TokenKind::Minus => Op::Subtract(operator),
+            TokenKind::Asterisk => Op::Multiply(operator),
+            TokenKind::Slash => Op::Divide(operator),
+            TokenKind::EqualEqual => Op::Equal(operator),
+            TokenKind::NotEqual => Op::NotEqual(operator),
+            TokenKind::GreaterThan => Op::Greater(operator),
+            TokenKind::GreaterThanEqual => Op::GreaterEqual(operator),
+            TokenKind::LessThan => Op::Less(operator),
+            TokenKind::LessThanEqual => Op::LessEqual(operator),
+            _ => {
+                self.error(&expr.operator, "Invalid binary operator");
+                return Ok(());
+            }
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    fn visit_variable_expr(&self, _: Rc<Expr>, expr: &VariableExpr) -> Result<(), Error> {
+        self.get_variable(&expr.name);
+        Ok(())
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            functions: RefCell::new(Vec::new()),
+            had_error: Cell::new(false),
+        }
+    }
+
+    /// Compiles a program into a function that runs it.
+    pub fn compile_script(&self, statements: &[Rc<Stmt>]) -> Rc<Prototype> {
+        let name = Token::new(TokenKind::Identifier, intern("script"), None, 0);
+        self.begin_function(name, Rc::new(Vec::new()), FunctionType::Script);
+        self.compile(statements);
+        self.end_function()
+    }
+
+    pub fn success(&self) -> bool {
+        !self.had_error.get()
+    }
+
+    fn compile(&self, statements: &[Rc<Stmt>]) {
+        for statement in statements {
+            self.compile_stmt(statement.clone());
+        }
+    }
+
+    fn compile_stmt(&self, stmt: Rc<Stmt>) {
+        let _ = stmt.accept(stmt.clone(), self);
+    }
+
+    fn compile_expr(&self, expr: Rc<Expr>) {
+        let _ = expr.accept(expr.clone(), self);
+    }
+
+    fn current(&self) -> RefMut<'_, FunctionState> {
+        RefMut::map(self.functions.borrow_mut(), |functions| {
+            functions.last_mut().expect("no function is being compiled")
+        })
+    }
+
+    fn emit(&self, op: Op) -> usize {
+        let mut function = self.current();
+        function.chunk.code.push(op);
+        function.chunk.code.len() - 1
+    }
+
+    fn add_token(&self, token: &Token) -> u32 {
+        let mut function = self.current();
+        function.chunk.tokens.push(token.clone());
+        function.chunk.tokens.len() as u32 - 1
+    }
+
+    fn add_layout(&self, layout: Layout) -> u32 {
+        let mut function = self.current();
+        function.chunk.layouts.push(layout);
+        function.chunk.layouts.len() as u32 - 1
+    }
+
+    // Points a forward jump at the next instruction.
+    fn patch_jump(&self, at: usize) {
+        let mut function = self.current();
+        let target = function.chunk.code.len() as u32;
+        match &mut function.chunk.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::JumpIfTrue(t) | Op::PopJumpIfFalse(t) => *t = target,
+            _ => panic!("patched an instruction that is not a jump"),
+        }
+    }
+
+    fn expect(&self, kind: Kind, expr: &Expr) {
+        if let Expr::Variable(v) = expr {
+            let name = self.add_token(&v.name);
+            self.emit(Op::Expect(kind, name));
+        }
+    }
+
+    fn begin_function(&self, name: Token, params: Rc<Vec<Token>>, function_type: FunctionType) {
+        // Slot 0 holds `this` in methods. Elsewhere it has no name.
+        let this = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            _ => "",
+        };
+        let scope_depth = if function_type == FunctionType::Script { 0 } else { 1 };
+        self.functions.borrow_mut().push(FunctionState {
+            name,
+            params: Rc::clone(&params),
+            function_type,
+            chunk: Chunk::default(),
+            locals: vec![Local {
+                name: this.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            captures: Vec::new(),
+            scope_depth,
+        });
+        for param in params.iter() {
+            self.add_local(&param.lexeme);
+        }
+    }
+
+    fn end_function(&self) -> Rc<Prototype> {
+        if self.current().function_type == FunctionType::Initializer {
+            self.emit(Op::GetLocal(0));
+        } else {
+            self.emit(Op::Nil);
+        }
+        self.emit(Op::Return);
+
+        let function = self.functions.borrow_mut().pop().expect("no function is being compiled");
+        Rc::new(Prototype {
+            name: function.name,
+            params: function.params,
+            is_initializer: function.function_type == FunctionType::Initializer,
+            chunk: function.chunk,
+            captures: function.captures,
+        })
+    }
+
+    // Compiles a function and emits the instruction that makes a closure
+    // of it.
+    fn function(&self, declaration: &FunctionStmt, function_type: FunctionType) {
+        self.begin_function(declaration.name.clone(), Rc::clone(&declaration.params), function_type);
+        self.compile(&declaration.body);
+        let prototype = self.end_function();
+
+        let mut function = self.current();
+        function.chunk.prototypes.push(prototype);
+        let index = function.chunk.prototypes.len() as u32 - 1;
+        drop(function);
+        self.emit(Op::Closure(index));
+    }
+
+    // Pushes a closure for each method, returning their names.
+    fn methods(&self, declarations: &[Rc<Stmt>], allow_initializer: bool) -> Vec<RcStr> {
+        let mut names = Vec::new();
+        for declaration in declarations {
+            if let Stmt::Function(method) = declaration.deref() {
+                let function_type = if allow_initializer && method.name.lexeme == "init" {
+                    FunctionType::Initializer
+                } else {
+                    FunctionType::Method
+                };
+                self.function(method, function_type);
+                names.push(method.name.lexeme.clone());
+            }
+        }
+        names
+    }
+
+    fn signatures(declarations: &[Rc<Stmt>]) -> Vec<(String, usize)> {
+        declarations
+            .iter()
+            .filter_map(|declaration| match declaration.deref() {
+                Stmt::Function(func) => Some((func.name.lexeme.to_string(), func.params.len())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn begin_scope(&self) {
+        self.current().scope_depth += 1;
+    }
+
+    // Locals leave the stack at the end of their scope. Captured ones move
+    // into their upvalues first.
+    fn end_scope(&self) {
+        let mut function = self.current();
+        function.scope_depth -= 1;
+        while let Some(local) = function.locals.last() {
+            if local.depth <= function.scope_depth {
+                break;
+            }
+            let op = if local.captured { Op::CloseUpvalue } else { Op::Pop };
+            function.chunk.code.push(op);
+            function.locals.pop();
+        }
+    }
+
+    // The value on top of the stack becomes the local's slot.
+    fn add_local(&self, name: &str) {
+        let mut function = self.current();
+        let depth = function.scope_depth;
+        function.locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+        });
+    }
+
+    // Defines a variable from the value on top of the stack: a global at
+    // the top level of the script, a local anywhere else.
+    fn define_variable(&self, name: &Token) {
+        if self.current().scope_depth > 0 {
+            self.add_local(&name.lexeme);
+        } else {
+            let name = self.add_token(name);
+            self.emit(Op::DefineGlobal(name));
+        }
+    }
+
+    fn get_variable(&self, name: &Token) {
+        let op = match self.resolve(name) {
+            Variable::Local(slot) => Op::GetLocal(slot),
+            Variable::Upvalue(index) => Op::GetUpvalue(index),
+            Variable::Global(name) => Op::GetGlobal(name),
+        };
+        self.emit(op);
+    }
+
+    fn set_variable(&self, name: &Token) {
+        let op = match self.resolve(name) {
+            Variable::Local(slot) => Op::SetLocal(slot),
+            Variable::Upvalue(index) => Op::SetUpvalue(index),
+            Variable::Global(name) => Op::SetGlobal(name),
+        };
+        self.emit(op);
+    }
+
+    fn resolve(&self, name: &Token) -> Variable {
+        let mut functions = self.functions.borrow_mut();
+        let depth = functions.len() - 1;
+        if let Some(slot) = Self::resolve_local(&functions[depth], &name.lexeme) {
+            return Variable::Local(slot);
+        }
+        if let Some(index) = Self::resolve_upvalue(&mut functions, depth, &name.lexeme) {
+            return Variable::Upvalue(index);
+        }
+        drop(functions);
+        Variable::Global(self.add_token(name))
+    }
+
+    fn resolve_local(function: &FunctionState, name: &str) -> Option<u32> {
+        function
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u32)
+    }
+
+    // Finds `name` in the functions enclosing the one at `depth`, adding
+    // an upvalue to each function between there and here.
+    fn resolve_upvalue(functions: &mut [FunctionState], depth: usize, name: &str) -> Option<u32> {
+        if depth == 0 {
+            return None;
+        }
+        let capture = if let Some(slot) = Self::resolve_local(&functions[depth - 1], name) {
+            functions[depth - 1].locals[slot as usize].captured = true;
+            Capture::Local(slot)
+        } else {
+            Capture::Upvalue(Self::resolve_upvalue(functions, depth - 1, name)?)
+        };
+
+        let captures = &mut functions[depth].captures;
+        match captures.iter().position(|c| *c == capture) {
+            Some(index) => Some(index as u32),
+            None => {
+                captures.push(capture);
+                Some(captures.len() as u32 - 1)
+            }
+        }
+    }
+
+    fn error(&self, token: &Token, message: &str) {
+        self.had_error.set(true);
+        Error::runtime_error(token, message);
+    }
+}
@@ -0,0 +1,495 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::module::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime};
+
+// Dates are objects holding the instant as `timestamp` (seconds since the
+// Unix epoch, UTC) and the UTC offset in minutes it is shown in. The
+// calendar fields are derived from those two. Durations are plain numbers
+// of seconds, so date arithmetic is ordinary arithmetic.
+
+const MS_PER_DAY: i64 = 86_400_000;
+// Instants further than this many milliseconds from the epoch are far
+// outside the years 1 to 9999, and are rejected before any arithmetic on
+// them can overflow.
+const MAX_MS: i64 = 1 << 53;
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+pub fn module() -> ModuleStruct {
+    let mut members = HashMap::new();
+    for native in [
+        NativeFunction::new("now", 0, native_now).with_keywords(&["offset"]),
+        NativeFunction::variadic("date", 3, native_date).with_keywords(&["offset"]),
+        NativeFunction::new("fromTimestamp", 1, native_from_timestamp).with_keywords(&["offset"]),
+        NativeFunction::new("parse", 2, native_parse),
+        NativeFunction::new("format", 2, native_format),
+        NativeFunction::new("withOffset", 2, native_with_offset),
+        NativeFunction::new("add", 2, native_add),
+        NativeFunction::new("diff", 2, native_diff),
+        NativeFunction::variadic("duration", 0, native_duration)
+            .with_keywords(&["days", "hours", "minutes", "seconds", "milliseconds"]),
+        NativeFunction::new("monotonic", 0, native_monotonic),
+    ] {
+        members.insert(native.name().to_string(), native.into_object());
+    }
+    ModuleStruct::new("time", members)
+}
+
+/// A date broken into calendar fields, in the time zone of its offset.
+struct Civil {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    millisecond: i64,
+    offset: i64,
+}
+
+impl Civil {
+    fn from_instant(ms: i64, offset: i64) -> Civil {
+        let local = ms + offset * 60_000;
+        let days = local.div_euclid(MS_PER_DAY);
+        let of_day = local.rem_euclid(MS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Civil {
+            year,
+            month,
+            day,
+            hour: of_day / 3_600_000,
+            minute: of_day / 60_000 % 60,
+            second: of_day / 1000 % 60,
+            millisecond: of_day % 1000,
+            offset,
+        }
+    }
+
+    /// Milliseconds since the epoch.
+    fn instant(&self) -> i64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        days * MS_PER_DAY + self.hour * 3_600_000 + self.minute * 60_000 + self.second * 1000 + self.millisecond
+            - self.offset * 60_000
+    }
+
+    fn days(&self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    // 1970-01-01 was a Thursday.
+    fn weekday(&self) -> i64 {
+        (self.days() + 4).rem_euclid(7)
+    }
+
+    fn yearday(&self) -> i64 {
+        self.days() - days_from_civil(self.year, 1, 1) + 1
+    }
+
+    fn validate(&self, native: &str) -> Result<(), Error> {
+        let checks = [
+            (1 <= self.year && self.year <= 9999, "year must be between 1 and 9999"),
+            (1 <= self.month && self.month <= 12, "month must be between 1 and 12"),
+            (
+                1 <= self.day && self.day <= days_in_month(self.year, self.month),
+                "day is out of range for the month",
+            ),
+            ((0..24).contains(&self.hour), "hour must be between 0 and 23"),
+            ((0..60).contains(&self.minute), "minute must be between 0 and 59"),
+            ((0..60).contains(&self.second), "second must be between 0 and 59"),
+            (self.offset.abs() < 24 * 60, "offset must be less than a day"),
+        ];
+        match checks.iter().find(|(ok, _)| !ok) {
+            Some((_, message)) => Err(Error::call_error(&format!("{native}(): {message}."))),
+            None => Ok(()),
+        }
+    }
+}
+
+// Howard Hinnant's algorithms for the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn date_object(terp: &Interpreter, ms: i64, offset: i64, native: &str) -> Result<Object, Error> {
+    let civil = Civil::from_instant(ms, offset);
+    civil.validate(native)?;
+    record(
+        terp,
+        "Date",
+        vec![
+            ("year", Object::Num(civil.year as f64)),
+            ("month", Object::Num(civil.month as f64)),
+            ("day", Object::Num(civil.day as f64)),
+            ("hour", Object::Num(civil.hour as f64)),
+            ("minute", Object::Num(civil.minute as f64)),
+            ("second", Object::Num(civil.second as f64)),
+            ("millisecond", Object::Num(civil.millisecond as f64)),
+            ("weekday", Object::Num(civil.weekday() as f64)),
+            ("yearday", Object::Num(civil.yearday() as f64)),
+            ("offset", Object::Num(offset as f64)),
+            ("timestamp", Object::Num(ms as f64 / 1000.0)),
+        ],
+    )
+}
+
+/// Milliseconds from seconds, unless they are NaN or further than `MAX_MS`
+/// from the epoch.
+fn millis(seconds: f64) -> Option<i64> {
+    let ms = (seconds * 1000.0).round();
+    (ms.abs() <= MAX_MS as f64).then_some(ms as i64)
+}
+
+/// The instant in milliseconds and the offset in minutes of a date object.
+fn date_arg(terp: &Interpreter, value: &Object, native: &str) -> Result<(i64, i64), Error> {
+    let expected = || Error::call_error(&format!("{native}() expects a date."));
+    let Object::Instance(instance) = value else {
+        return Err(expected());
+    };
+    let field = |name: &str| -> Result<f64, Error> {
+        if !instance.has(name) {
+            return Err(expected());
+        }
+        let token = Token::new(TokenKind::Identifier, name.into(), None, 0);
+        match instance.get(&token, instance, terp)? {
+            Object::Num(n) if n.is_finite() => Ok(n),
+            _ => Err(expected()),
+        }
+    };
+    let out_of_range = || Error::call_error(&format!("{native}() got a date out of range."));
+    let ms = millis(field("timestamp")?).ok_or_else(out_of_range)?;
+    match field("offset")? {
+        offset if offset.fract() == 0.0 && offset.abs() < (24 * 60) as f64 => Ok((ms, offset as i64)),
+        _ => Err(out_of_range()),
+    }
+}
+
+fn offset_arg(value: &Object, native: &str) -> Result<i64, Error> {
+    match value {
+        Object::Nil => Ok(0),
+        Object::Num(n) if n.fract() == 0.0 && n.abs() < (24 * 60) as f64 => Ok(*n as i64),
+        Object::Num(n) if n.fract() == 0.0 => Err(Error::call_error(&format!(
+            "{native}(): offset must be less than a day."
+        ))),
+        _ => Err(Error::call_error(&format!(
+            "{native}() expects the offset in whole minutes."
+        ))),
+    }
+}
+
+fn now_ms() -> Result<i64, Error> {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_millis() as i64)
+        .map_err(|_| Error::call_error("Failed to get time."))
+}
+
+fn native_now(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let offset = offset_arg(&args[0], "time.now")?;
+    date_object(terp, now_ms()?, offset, "time.now")
+}
+
+// time.date(year, month, day, hour, minute, second, offset: minutes)
+fn native_date(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (fields, offset) = args.split_at(args.len() - 1);
+    if fields.len() > 6 {
+        return Err(Error::call_error(&format!(
+            "Expected at most 6 arguments but got {}.",
+            fields.len()
+        )));
+    }
+    let mut values = [1970, 1, 1, 0, 0, 0];
+    for (i, field) in fields.iter().enumerate() {
+        match field {
+            Object::Num(n) if n.fract() == 0.0 => values[i] = *n as i64,
+            _ => return Err(Error::call_error("time.date() expects whole numbers.")),
+        }
+    }
+    let civil = Civil {
+        year: values[0],
+        month: values[1],
+        day: values[2],
+        hour: values[3],
+        minute: values[4],
+        second: values[5],
+        millisecond: 0,
+        offset: offset_arg(&offset[0], "time.date")?,
+    };
+    civil.validate("time.date")?;
+    date_object(terp, civil.instant(), civil.offset, "time.date")
+}
+
+fn native_from_timestamp(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let seconds = number_arg(&args[0], "time.fromTimestamp")?;
+    let offset = offset_arg(&args[1], "time.fromTimestamp")?;
+    if !seconds.is_finite() {
+        return Err(Error::call_error("time.fromTimestamp() expects a finite number."));
+    }
+    let ms = millis(seconds)
+        .ok_or_else(|| Error::call_error("time.fromTimestamp() got a timestamp out of range."))?;
+    date_object(terp, ms, offset, "time.fromTimestamp")
+}
+
+fn native_with_offset(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (ms, _) = date_arg(terp, &args[0], "time.withOffset")?;
+    let offset = offset_arg(&args[1], "time.withOffset")?;
+    date_object(terp, ms, offset, "time.withOffset")
+}
+
+fn native_add(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (ms, offset) = date_arg(terp, &args[0], "time.add")?;
+    let seconds = number_arg(&args[1], "time.add")?;
+    if !seconds.is_finite() {
+        return Err(Error::call_error("time.add() expects a finite number of seconds."));
+    }
+    let ms = millis(seconds)
+        .and_then(|delta| ms.checked_add(delta))
+        .ok_or_else(|| Error::call_error("time.add() result is out of range."))?;
+    date_object(terp, ms, offset, "time.add")
+}
+
+fn native_diff(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (a, _) = date_arg(terp, &args[0], "time.diff")?;
+    let (b, _) = date_arg(terp, &args[1], "time.diff")?;
+    let ms = a
+        .checked_sub(b)
+        .ok_or_else(|| Error::call_error("time.diff() result is out of range."))?;
+    Ok(Object::Num(ms as f64 / 1000.0))
+}
+
+fn native_duration(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    if args.len() > 5 {
+        return Err(Error::call_error(
+            "time.duration() takes only keyword arguments: days, hours, minutes, seconds, milliseconds.",
+        ));
+    }
+    let mut seconds = 0.0;
+    for (value, scale) in args.iter().zip([86_400.0, 3600.0, 60.0, 1.0, 0.001]) {
+        if *value != Object::Nil {
+            seconds += number_arg(value, "time.duration")? * scale;
+        }
+    }
+    Ok(Object::Num(seconds))
+}
+
+// Milliseconds from an arbitrary starting point, for measuring intervals.
+// Unlike clock() it never goes backwards.
+fn native_monotonic(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let start = START.get_or_init(Instant::now);
+    Ok(Object::Num(start.elapsed().as_nanos() as f64 / 1_000_000.0))
+}
+
+fn native_format(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (ms, offset) = date_arg(terp, &args[0], "time.format")?;
+    let format = string_arg(&args[1], "time.format")?;
+    let civil = Civil::from_instant(ms, offset);
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let hour12 = if civil.hour % 12 == 0 { 12 } else { civil.hour % 12 };
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", civil.year)),
+            Some('y') => out.push_str(&format!("{:02}", civil.year % 100)),
+            Some('m') => out.push_str(&format!("{:02}", civil.month)),
+            Some('d') => out.push_str(&format!("{:02}", civil.day)),
+            Some('H') => out.push_str(&format!("{:02}", civil.hour)),
+            Some('I') => out.push_str(&format!("{:02}", hour12)),
+            Some('M') => out.push_str(&format!("{:02}", civil.minute)),
+            Some('S') => out.push_str(&format!("{:02}", civil.second)),
+            Some('f') => out.push_str(&format!("{:03}", civil.millisecond)),
+            Some('p') => out.push_str(if civil.hour < 12 { "AM" } else { "PM" }),
+            Some('j') => out.push_str(&format!("{:03}", civil.yearday())),
+            Some('a') => out.push_str(&WEEKDAYS[civil.weekday() as usize][..3]),
+            Some('A') => out.push_str(WEEKDAYS[civil.weekday() as usize]),
+            Some('b') => out.push_str(&MONTHS[civil.month as usize - 1][..3]),
+            Some('B') => out.push_str(MONTHS[civil.month as usize - 1]),
+            Some('z') => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                out.push_str(&format!("{sign}{:02}{:02}", offset.abs() / 60, offset.abs() % 60));
+            }
+            Some('s') => out.push_str(&ms.div_euclid(1000).to_string()),
+            Some('F') => out.push_str(&format!("{:04}-{:02}-{:02}", civil.year, civil.month, civil.day)),
+            Some('T') => out.push_str(&format!("{:02}:{:02}:{:02}", civil.hour, civil.minute, civil.second)),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                return Err(Error::call_error(&format!(
+                    "time.format() got unknown directive '%{other}'."
+                )))
+            }
+            None => return Err(Error::call_error("time.format() got a format ending in '%'.")),
+        }
+    }
+    Ok(Object::Str(out.into()))
+}
+
+struct DateParser<'a> {
+    input: &'a [char],
+    current: usize,
+}
+
+impl DateParser<'_> {
+    fn number(&mut self, max_digits: usize) -> Option<i64> {
+        let start = self.current;
+        while self.current - start < max_digits && self.input.get(self.current).is_some_and(|c| c.is_ascii_digit()) {
+            self.current += 1;
+        }
+        let digits: String = self.input[start..self.current].iter().collect();
+        digits.parse().ok()
+    }
+
+    // Matches one of `names` case-insensitively, returning its index.
+    fn name(&mut self, names: &[&str], abbreviated: bool) -> Option<usize> {
+        for (i, name) in names.iter().enumerate() {
+            let name = if abbreviated { &name[..3] } else { name };
+            let len = name.chars().count();
+            if let Some(candidate) = self.input.get(self.current..self.current + len) {
+                let candidate: String = candidate.iter().collect();
+                if candidate.eq_ignore_ascii_case(name) {
+                    self.current += len;
+                    return Some(i);
+                }
+            }
+        }
+        None
+    }
+
+    fn offset(&mut self) -> Option<i64> {
+        let sign = match self.input.get(self.current) {
+            Some('Z') => {
+                self.current += 1;
+                return Some(0);
+            }
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return None,
+        };
+        self.current += 1;
+        let hours = self.number(2)?;
+        if self.input.get(self.current) == Some(&':') {
+            self.current += 1;
+        }
+        let minutes = self.number(2)?;
+        Some(sign * (hours * 60 + minutes))
+    }
+}
+
+fn native_parse(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let text = string_arg(&args[0], "time.parse")?;
+    let format = string_arg(&args[1], "time.parse")?;
+    let input: Vec<char> = text.chars().collect();
+    let mismatch = || {
+        Error::call_error(&format!(
+            "time.parse() could not read '{text}' with format '{format}'."
+        ))
+    };
+
+    let mut parser = DateParser { input: &input, current: 0 };
+    let mut civil = Civil {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        millisecond: 0,
+        offset: 0,
+    };
+    let mut pm = None;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            if parser.input.get(parser.current) != Some(&c) {
+                return Err(mismatch());
+            }
+            parser.current += 1;
+            continue;
+        }
+        let directive = chars.next().ok_or_else(mismatch)?;
+        let parsed = match directive {
+            'Y' => parser.number(4).map(|n| civil.year = n),
+            'y' => parser.number(2).map(|n| civil.year = if n < 69 { 2000 + n } else { 1900 + n }),
+            'm' => parser.number(2).map(|n| civil.month = n),
+            'd' => parser.number(2).map(|n| civil.day = n),
+            'H' | 'I' => parser.number(2).map(|n| civil.hour = n),
+            'M' => parser.number(2).map(|n| civil.minute = n),
+            'S' => parser.number(2).map(|n| civil.second = n),
+            'f' => {
+                let start = parser.current;
+                parser.number(9).map(|n| {
+                    let digits = (parser.current - start) as i32;
+                    civil.millisecond = (n as f64 * 10f64.powi(3 - digits)).round() as i64;
+                })
+            }
+            'p' => parser.name(&["AM", "PM"], false).map(|i| pm = Some(i == 1)),
+            'a' => parser.name(&WEEKDAYS, true).map(|_| ()),
+            'A' => parser.name(&WEEKDAYS, false).map(|_| ()),
+            'b' => parser.name(&MONTHS, true).map(|i| civil.month = i as i64 + 1),
+            'B' => parser.name(&MONTHS, false).map(|i| civil.month = i as i64 + 1),
+            'z' => parser.offset().map(|offset| civil.offset = offset),
+            '%' => (parser.input.get(parser.current) == Some(&'%')).then(|| parser.current += 1),
+            other => {
+                return Err(Error::call_error(&format!(
+                    "time.parse() got unknown directive '%{other}'."
+                )))
+            }
+        };
+        parsed.ok_or_else(mismatch)?;
+    }
+    if parser.current != input.len() {
+        return Err(mismatch());
+    }
+    match pm {
+        Some(_) if !(1..=12).contains(&civil.hour) => {
+            return Err(Error::call_error("time.parse(): hour must be between 1 and 12 with %p."))
+        }
+        Some(pm) => civil.hour = civil.hour % 12 + if pm { 12 } else { 0 },
+        None => {}
+    }
+    civil.validate("time.parse")?;
+    date_object(terp, civil.instant(), civil.offset, "time.parse")
+}
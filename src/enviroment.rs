@@ -1,80 +1,139 @@
 use std::collections::HashMap;
 use crate::tokens::*;
 use crate::errors::Error;
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
 use std::rc::Rc;
 use std::cell::RefCell;
 
+/// Where the resolver found a local variable: how many scopes out from the
+/// one it is used in, and its index within that scope.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
 
+/// A local scope. Values live in the order they were defined, which is the
+/// order the resolver handed out slot indexes in.
 #[derive(Debug)]
 pub struct Environment {
-    values: HashMap<String, Object>,
+    values: Vec<Object>,
     enclosing: Option<Rc<RefCell<Environment>>>,
+    tracked: bool,
 }
 
 impl Environment {
-    pub fn new() -> Environment {
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Environment {
         Environment {
-            values: HashMap::new(),
-            enclosing: None,
+            values: Vec::new(),
+            enclosing,
+            tracked: false,
         }
     }
 
-    pub fn new_with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Environment {
-        Environment {
-            values: HashMap::new(),
-            enclosing: Some(enclosing),
+    /// Hands this scope and the ones around it to the collector. Only a
+    /// scope some closure has captured can be part of a cycle, so most
+    /// scopes never need tracking.
+    pub fn track(environment: &Rc<RefCell<Environment>>) {
+        let mut next = Some(Rc::clone(environment));
+        while let Some(environment) = next {
+            let mut scope = environment.borrow_mut();
+            if scope.tracked {
+                break;
+            }
+            scope.tracked = true;
+            next = scope.enclosing.clone();
+            drop(scope);
+            gc::track(&environment);
         }
     }
 
-    pub fn define(&mut self, name: String, value: Object) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, value: Object) {
+        self.values.push(value);
     }
 
-    pub fn get_at(&self, distance: usize, name: &str) -> Result<Object, Error> {
-        if distance == 0 {
-            Ok(self.values.get(name).unwrap().clone())
+    pub fn get_at(&self, depth: usize, index: usize) -> Object {
+        if depth == 0 {
+            self.values[index].clone()
         }else{
-            self.enclosing.as_ref().unwrap().borrow().get_at(distance - 1, name)
+            self.ancestor(depth).borrow().values[index].clone()
         }
     }
 
-    pub fn assign_at(&mut self, distance: usize, name: &Token, value: Object) -> Result<(), Error> {
-        if distance == 0 {
-            self.values.insert(name.lexeme.clone(), value);
-            Ok(())
+    pub fn assign_at(&mut self, depth: usize, index: usize, value: Object) {
+        if depth == 0 {
+            self.values[index] = value;
         }else{
-            self.enclosing.as_ref().unwrap().borrow_mut().assign_at(distance - 1, name, value)
+            self.ancestor(depth).borrow_mut().values[index] = value;
+        }
+    }
+
+    fn ancestor(&self, depth: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = Rc::clone(self.enclosing.as_ref().unwrap());
+        for _ in 1..depth {
+            let enclosing = Rc::clone(environment.borrow().enclosing.as_ref().unwrap());
+            environment = enclosing;
+        }
+        environment
+    }
+}
+
+impl Trace for RefCell<Environment> {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        let Ok(scope) = self.try_borrow_mut() else {
+            return false;
+        };
+        scope.values.iter().for_each(|value| gc::object_refs(value, refs));
+        if let Some(enclosing) = &scope.enclosing {
+            refs.push(gc::address(enclosing));
         }
+        true
+    }
+
+    fn clear(&self) {
+        let mut scope = self.borrow_mut();
+        scope.values.clear();
+        scope.enclosing = None;
+    }
+
+    fn is_value(&self) -> bool {
+        false
+    }
+}
+
+/// Top-level variables, natives and modules. These are looked up by name
+/// because the resolver leaves them unresolved.
+#[derive(Debug, Default)]
+pub struct Globals {
+    values: HashMap<RcStr, Object>,
+}
+
+impl Globals {
+    pub fn define(&mut self, name: RcStr, value: Object) {
+        self.values.insert(name, value);
     }
 
     pub fn get(&self, token: &Token) -> Result<Object, Error> {
-        if let Some(object) = self.values.get(&token.lexeme) {
-            Ok(object.clone())
-        } else if let Some(enclosing) = &self.enclosing {
-            enclosing.borrow().get(token)
-        } else {
-            Err(Error::parse_error(
+        match self.values.get(&token.lexeme) {
+            Some(object) => Ok(object.clone()),
+            None => Err(Error::parse_error(
                 token,
                 &format!("Undefined variable '{}'.", token.lexeme),
-            ))
+            )),
         }
     }
-    
+
     pub fn assign(&mut self, name: &Token, value: Object) -> Result<(), Error> {
         match self.values.get_mut(&name.lexeme) {
             Some(v) => {
                 *v = value;
                 Ok(())
             }
-            None => {
-                match &self.enclosing {
-                    Some(env) => env.borrow_mut().assign(name, value),
-                    None => Err(Error::parse_error(
-                        name,
-                        &format!("Undefined variable '{}'.", name.lexeme),
-                    )),
-                }
-            }
+            None => Err(Error::parse_error(
+                name,
+                &format!("Undefined variable '{}'.", name.lexeme),
+            )),
         }
     }
 }
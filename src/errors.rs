@@ -1,12 +1,22 @@
+use crate::functions::Function;
+use crate::sandbox::Limit;
 use crate::tokens::*;
+use std::rc::Rc;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
     LexerError{ lexeme: String , line: String, message: String},
     ParseError { token: Token, message: String },
     RuntimeError { token: Token, message: String },
     SystemError { message: String },
+    CallError { message: String },
+    /// A sandboxed script ran into one of its limits.
+    LimitError { limit: Limit, message: String },
     Return { value: Object },
+    /// A call in tail position, handed back unmade to the function that
+    /// returns its result so the callee runs in that function's place.
+    TailCall { function: Rc<Function>, arguments: Vec<Object> },
 }
 
 impl Error {
@@ -24,6 +34,10 @@ impl Error {
         Error::Return { value }
     }
 
+    pub fn tail_call(function: Rc<Function>, arguments: Vec<Object>) -> Error {
+        Error::TailCall { function, arguments }
+    }
+
     pub fn parse_error(token: &Token, message: &str) -> Error {
         let err = Error::ParseError {
             token: token.clone(),
@@ -50,6 +64,24 @@ impl Error {
         err
     }
 
+    /// An error raised inside a call that has no token to point at, such as
+    /// a native function. It is not reported here: the interpreter turns it
+    /// into a runtime error at the call site.
+    pub fn call_error(message: &str) -> Error {
+        Error::CallError {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn limit_error(limit: Limit, message: &str) -> Error {
+        let err = Error::LimitError {
+            limit,
+            message: message.to_string(),
+        };
+        err.report("");
+        err
+    }
+
     pub fn report(&self, _loc: &str) {
         match self {
             Error::LexerError { lexeme, line, message } => {
@@ -66,7 +98,13 @@ impl Error {
             Error::SystemError { message } => {
                 eprintln!("System Error: {message}");
             }
-            Error::Return { .. } => {}
+            Error::CallError { message } => {
+                eprintln!("Error: {message}");
+            }
+            Error::LimitError { message, .. } => {
+                eprintln!("Limit Error: {message}");
+            }
+            Error::Return { .. } | Error::TailCall { .. } => {}
         };
     }
 }
\ No newline at end of file
@@ -1,5 +1,7 @@
+use crate::enviroment::*;
 use crate::errors::*;
 use crate::tokens::*;
+use std::cell::Cell;
 use std::rc::Rc;
 
 pub enum Expr {
@@ -8,9 +10,13 @@ pub enum Expr {
     Call(Rc<CallExpr>),
     Get(Rc<GetExpr>),
     Grouping(Rc<GroupingExpr>),
+    Index(Rc<IndexExpr>),
+    List(Rc<ListExpr>),
     Literal(Rc<LiteralExpr>),
     Logical(Rc<LogicalExpr>),
+    Map(Rc<MapExpr>),
     Set(Rc<SetExpr>),
+    SetIndex(Rc<SetIndexExpr>),
     Super(Rc<SuperExpr>),
     This(Rc<ThisExpr>),
     Unary(Rc<UnaryExpr>),
@@ -25,9 +31,13 @@ impl PartialEq for Expr {
             (Expr::Call(a), Expr::Call(b)) => Rc::ptr_eq(a, b),
             (Expr::Get(a), Expr::Get(b)) => Rc::ptr_eq(a, b),
             (Expr::Grouping(a), Expr::Grouping(b)) => Rc::ptr_eq(a, b),
+            (Expr::Index(a), Expr::Index(b)) => Rc::ptr_eq(a, b),
+            (Expr::List(a), Expr::List(b)) => Rc::ptr_eq(a, b),
             (Expr::Literal(a), Expr::Literal(b)) => Rc::ptr_eq(a, b),
             (Expr::Logical(a), Expr::Logical(b)) => Rc::ptr_eq(a, b),
+            (Expr::Map(a), Expr::Map(b)) => Rc::ptr_eq(a, b),
             (Expr::Set(a), Expr::Set(b)) => Rc::ptr_eq(a, b),
+            (Expr::SetIndex(a), Expr::SetIndex(b)) => Rc::ptr_eq(a, b),
             (Expr::Super(a), Expr::Super(b)) => Rc::ptr_eq(a, b),
             (Expr::This(a), Expr::This(b)) => Rc::ptr_eq(a, b),
             (Expr::Unary(a), Expr::Unary(b)) => Rc::ptr_eq(a, b),
@@ -49,9 +59,13 @@ impl Hash for Expr {
         Expr::Call(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Get(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Grouping(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Expr::Index(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Expr::List(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Literal(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Logical(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Expr::Map(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Set(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Expr::SetIndex(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Super(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::This(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Expr::Unary(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
@@ -68,9 +82,13 @@ impl Expr {
             Expr::Call(v) => expr_visitor.visit_call_expr(wrapper, v),
             Expr::Get(v) => expr_visitor.visit_get_expr(wrapper, v),
             Expr::Grouping(v) => expr_visitor.visit_grouping_expr(wrapper, v),
+            Expr::Index(v) => expr_visitor.visit_index_expr(wrapper, v),
+            Expr::List(v) => expr_visitor.visit_list_expr(wrapper, v),
             Expr::Literal(v) => expr_visitor.visit_literal_expr(wrapper, v),
             Expr::Logical(v) => expr_visitor.visit_logical_expr(wrapper, v),
+            Expr::Map(v) => expr_visitor.visit_map_expr(wrapper, v),
             Expr::Set(v) => expr_visitor.visit_set_expr(wrapper, v),
+            Expr::SetIndex(v) => expr_visitor.visit_setindex_expr(wrapper, v),
             Expr::Super(v) => expr_visitor.visit_super_expr(wrapper, v),
             Expr::This(v) => expr_visitor.visit_this_expr(wrapper, v),
             Expr::Unary(v) => expr_visitor.visit_unary_expr(wrapper, v),
@@ -82,6 +100,7 @@ impl Expr {
 pub struct AssignExpr {
     pub name: Token,
     pub value: Rc<Expr>,
+    pub slot: Cell<Option<Slot>>,
 }
 
 pub struct BinaryExpr {
@@ -94,6 +113,9 @@ pub struct CallExpr {
     pub callee: Rc<Expr>,
     pub paren: Token,
     pub arguments: Vec<Rc<Expr>>,
+    pub keywords: Vec<Token>,
+    pub keyword_values: Vec<Rc<Expr>>,
+    pub tail: Cell<bool>,
 }
 
 pub struct GetExpr {
@@ -105,6 +127,17 @@ pub struct GroupingExpr {
     pub expression: Rc<Expr>,
 }
 
+pub struct IndexExpr {
+    pub object: Rc<Expr>,
+    pub bracket: Token,
+    pub index: Rc<Expr>,
+}
+
+pub struct ListExpr {
+    pub bracket: Token,
+    pub elements: Vec<Rc<Expr>>,
+}
+
 pub struct LiteralExpr {
     pub value: Option<Object>,
 }
@@ -115,19 +148,34 @@ pub struct LogicalExpr {
     pub right: Rc<Expr>,
 }
 
+pub struct MapExpr {
+    pub brace: Token,
+    pub keys: Vec<Rc<Expr>>,
+    pub values: Vec<Rc<Expr>>,
+}
+
 pub struct SetExpr {
     pub object: Rc<Expr>,
     pub name: Token,
     pub value: Rc<Expr>,
 }
 
+pub struct SetIndexExpr {
+    pub object: Rc<Expr>,
+    pub bracket: Token,
+    pub index: Rc<Expr>,
+    pub value: Rc<Expr>,
+}
+
 pub struct SuperExpr {
     pub keyword: Token,
     pub method: Token,
+    pub slot: Cell<Option<Slot>>,
 }
 
 pub struct ThisExpr {
     pub keyword: Token,
+    pub slot: Cell<Option<Slot>>,
 }
 
 pub struct UnaryExpr {
@@ -137,6 +185,7 @@ pub struct UnaryExpr {
 
 pub struct VariableExpr {
     pub name: Token,
+    pub slot: Cell<Option<Slot>>,
 }
 
 pub trait ExprVisitor<T> {
@@ -145,9 +194,13 @@ pub trait ExprVisitor<T> {
     fn visit_call_expr(&self, wrapper: Rc<Expr>, expr: &CallExpr) -> Result<T, Error>;
     fn visit_get_expr(&self, wrapper: Rc<Expr>, expr: &GetExpr) -> Result<T, Error>;
     fn visit_grouping_expr(&self, wrapper: Rc<Expr>, expr: &GroupingExpr) -> Result<T, Error>;
+    fn visit_index_expr(&self, wrapper: Rc<Expr>, expr: &IndexExpr) -> Result<T, Error>;
+    fn visit_list_expr(&self, wrapper: Rc<Expr>, expr: &ListExpr) -> Result<T, Error>;
     fn visit_literal_expr(&self, wrapper: Rc<Expr>, expr: &LiteralExpr) -> Result<T, Error>;
     fn visit_logical_expr(&self, wrapper: Rc<Expr>, expr: &LogicalExpr) -> Result<T, Error>;
+    fn visit_map_expr(&self, wrapper: Rc<Expr>, expr: &MapExpr) -> Result<T, Error>;
     fn visit_set_expr(&self, wrapper: Rc<Expr>, expr: &SetExpr) -> Result<T, Error>;
+    fn visit_setindex_expr(&self, wrapper: Rc<Expr>, expr: &SetIndexExpr) -> Result<T, Error>;
     fn visit_super_expr(&self, wrapper: Rc<Expr>, expr: &SuperExpr) -> Result<T, Error>;
     fn visit_this_expr(&self, wrapper: Rc<Expr>, expr: &ThisExpr) -> Result<T, Error>;
     fn visit_unary_expr(&self, wrapper: Rc<Expr>, expr: &UnaryExpr) -> Result<T, Error>;
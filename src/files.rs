@@ -0,0 +1,113 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::fs;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("readFile", 1, native_read_file),
+        NativeFunction::new("writeFile", 2, native_write_file),
+        NativeFunction::new("appendFile", 2, native_append_file),
+        NativeFunction::new("readLines", 1, native_read_lines),
+        NativeFunction::new("exists", 1, native_exists),
+        NativeFunction::new("listDir", 1, native_list_dir),
+        NativeFunction::new("mkdir", 1, native_mkdir),
+        NativeFunction::new("remove", 1, native_remove),
+        NativeFunction::new("stat", 1, native_stat),
+    ]
+}
+
+// Turns an I/O failure into a runtime error naming the native and the path.
+fn io_error(native: &str, path: &str, error: io::Error) -> Error {
+    Error::call_error(&format!("{native}() failed for '{path}': {error}."))
+}
+
+fn native_read_file(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "readFile")?;
+    fs::read_to_string(path)
+        .map(|contents| Object::Str(contents.into()))
+        .map_err(|e| io_error("readFile", path, e))
+}
+
+fn native_write_file(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "writeFile")?;
+    let contents = string_arg(&args[1], "writeFile")?;
+    fs::write(path, contents).map_err(|e| io_error("writeFile", path, e))?;
+    Ok(Object::Nil)
+}
+
+fn native_append_file(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "appendFile")?;
+    let contents = string_arg(&args[1], "appendFile")?;
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| io_error("appendFile", path, e))?;
+    Ok(Object::Nil)
+}
+
+fn native_read_lines(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "readLines")?;
+    let contents = fs::read_to_string(path).map_err(|e| io_error("readLines", path, e))?;
+    let lines = contents.lines().map(|line| Object::Str(line.into())).collect();
+    Ok(Object::list(lines))
+}
+
+fn native_exists(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "exists")?;
+    Ok(Object::Bool(fs::metadata(path).is_ok()))
+}
+
+fn native_list_dir(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "listDir")?;
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path).map_err(|e| io_error("listDir", path, e))? {
+        let entry = entry.map_err(|e| io_error("listDir", path, e))?;
+        names.push(entry.file_name().to_string_lossy().into_owned());
+    }
+    names.sort();
+    let names = names.into_iter().map(|name| Object::Str(name.into())).collect();
+    Ok(Object::list(names))
+}
+
+fn native_mkdir(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "mkdir")?;
+    fs::create_dir_all(path).map_err(|e| io_error("mkdir", path, e))?;
+    Ok(Object::Nil)
+}
+
+// Directories are only removed when empty, so a wrong path can't wipe a tree.
+fn native_remove(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "remove")?;
+    let result = match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+        _ => fs::remove_file(path),
+    };
+    result.map_err(|e| io_error("remove", path, e))?;
+    Ok(Object::Nil)
+}
+
+fn native_stat(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "stat")?;
+    let metadata = fs::metadata(path).map_err(|e| io_error("stat", path, e))?;
+    let modified = match metadata.modified().map(|t| t.duration_since(SystemTime::UNIX_EPOCH)) {
+        Ok(Ok(since)) => Object::Num(since.as_millis() as f64),
+        _ => Object::Nil,
+    };
+    record(
+        terp,
+        "Stat",
+        vec![
+            ("size", Object::Num(metadata.len() as f64)),
+            ("isFile", Object::Bool(metadata.is_file())),
+            ("isDir", Object::Bool(metadata.is_dir())),
+            ("readonly", Object::Bool(metadata.permissions().readonly())),
+            ("modified", modified),
+        ],
+    )
+}
@@ -1,8 +1,10 @@
 use crate::callable::*;
 use crate::enviroment::Environment;
 use crate::errors::*;
+use crate::gc::{self, Trace};
 use crate::stmt::*;
 use crate::tokens::*;
+use crate::vm::{self, Closure};
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::fmt;
@@ -13,8 +15,23 @@ pub struct Function {
     name : Token,
     params : Rc<Vec<Token>>,
     is_initializer: bool,
-    body : Rc<Vec<Rc<Stmt>>>,
-    closure: Rc<RefCell<Environment>>,
+    code: Code,
+    owner: Option<usize>,
+}
+
+/// What a function runs: statements for the tree-walking interpreter, or
+/// bytecode for the VM.
+#[derive(Clone)]
+enum Code {
+    Tree {
+        body: Rc<Vec<Rc<Stmt>>>,
+        // None for functions declared at the top level, which only see globals.
+        closure: Option<Rc<RefCell<Environment>>>,
+    },
+    Compiled {
+        closure: Rc<Closure>,
+        this: Option<Object>,
+    },
 }
 
 impl fmt::Debug for Function {
@@ -29,62 +46,122 @@ impl Clone for Function {
             name: self.name.clone(),
             is_initializer: self.is_initializer,
             params: Rc::clone(&self.params),
-            body: Rc::clone(&self.body),
-            closure: Rc::clone(&self.closure),
+            code: self.code.clone(),
+            owner: self.owner,
         }
     }
 }
 
 impl PartialEq for Function {
     fn eq(&self, other: &Self) -> bool {
-        self.name.kind == other.name.kind && Rc::ptr_eq(&self.params, &other.params) && Rc::ptr_eq(&self.body, &other.body) && Rc::ptr_eq(&self.closure, &other.closure)     
+        let same_code = match (&self.code, &other.code) {
+            (Code::Tree { body, closure }, Code::Tree { body: other_body, closure: other_closure }) => {
+                Rc::ptr_eq(body, other_body) && match (closure, other_closure) {
+                    (Some(closure), Some(other_closure)) => Rc::ptr_eq(closure, other_closure),
+                    (None, None) => true,
+                    _ => false,
+                }
+            }
+            // Like a tree-walked method, each binding of `this` is a new function.
+            (Code::Compiled { closure, this: None }, Code::Compiled { closure: other_closure, this: None }) => {
+                Rc::ptr_eq(closure, other_closure)
+            }
+            _ => false,
+        };
+        self.name.kind == other.name.kind && Rc::ptr_eq(&self.params, &other.params) && same_code
     }
 }
 
 impl Function {
-    pub fn new(declaration: &FunctionStmt, closure: &Rc<RefCell<Environment>>, is_initializer: bool) -> Self {
-        Self {
+    /// `owner` is the id of the class whose body lexically contains the
+    /// function, which decides what private members it may touch.
+    pub fn new(declaration: &FunctionStmt, closure: &Option<Rc<RefCell<Environment>>>, is_initializer: bool, owner: Option<usize>) -> Rc<Self> {
+        if let Some(closure) = closure {
+            Environment::track(closure);
+        }
+        Self::track(Self {
             name: declaration.name.clone(),
             params: Rc::clone(&declaration.params),
-            body: Rc::clone(&declaration.body),
-            closure: Rc::clone(&closure),
-            is_initializer: is_initializer,
+            code: Code::Tree {
+                body: Rc::clone(&declaration.body),
+                closure: closure.clone(),
+            },
+            is_initializer,
+            owner,
+        })
+    }
+
+    /// A function the VM runs, made from a closure over compiled code.
+    pub fn compiled(closure: Rc<Closure>, owner: Option<usize>) -> Rc<Self> {
+        let prototype = &closure.prototype;
+        Self::track(Self {
+            name: prototype.name.clone(),
+            params: Rc::clone(&prototype.params),
+            is_initializer: prototype.is_initializer,
+            code: Code::Compiled {
+                closure: Rc::clone(&closure),
+                this: None,
+            },
+            owner,
+        })
+    }
+
+    /// A copy of this function that belongs to another class. The VM
+    /// creates methods before the class they belong to.
+    pub fn with_owner(&self, owner: Option<usize>) -> Rc<Self> {
+        Self::track(Self {
+            owner,
+            ..self.clone()
+        })
+    }
+
+    fn track(function: Self) -> Rc<Self> {
+        let function = Rc::new(function);
+        gc::track(&function);
+        function
+    }
+
+    pub fn owner(&self) -> Option<usize> {
+        self.owner
+    }
+
+    /// The closure and bound `this` of a function the VM runs.
+    pub fn bytecode(&self) -> Option<(&Rc<Closure>, Option<&Object>)> {
+        match &self.code {
+            Code::Compiled { closure, this } => Some((closure, this.as_ref())),
+            Code::Tree { .. } => None,
         }
     }
 
-    pub fn bind(&self, instance: &Object) -> Object {
-        let e = RefCell::new(Environment::new_with_enclosing(Rc::clone(&self.closure)));
-        e.borrow_mut().define("this".to_string(), instance.clone());
-        Object::Function(Rc::new(Self {
-            name: self.name.clone(),
-            is_initializer: self.is_initializer,
-            params: Rc::clone(&self.params),
-            body: Rc::clone(&self.body),
-            closure: Rc::new(e),
-       }))
+    // The instance an initializer was bound to.
+    fn this(closure: &Option<Rc<RefCell<Environment>>>) -> Object {
+        closure.as_ref().unwrap().borrow().get_at(0, 0)
     }
-}
 
-impl CallableTrait for Function {
+    // Runs the body of a tree-walked function. A tail call it ends with is
+    // passed back to the caller as an error.
+    fn run(&self, interpreter: &Interpreter, arguments: &[Object]) -> Result<Object, Error> {
+        let Code::Tree { body, closure } = &self.code else {
+            unreachable!("compiled functions run on the VM");
+        };
 
-    fn call(&self, interpreter: &Interpreter, arguments: &Vec<Object>, _class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
-        let mut e = Environment::new_with_enclosing(Rc::clone(&self.closure));
+        let mut e = Environment::new(closure.clone());
 
-        for (param , arg) in self.params.iter().zip(arguments.iter()) {
-            e.define(param.lexeme.clone(), arg.clone());
+        for arg in arguments.iter().take(self.params.len()) {
+            e.define(arg.clone());
         }
 
-        match interpreter.execute_block(&self.body, e) {
+        match interpreter.execute_block(body, e) {
             Ok(_) => {
                 if self.is_initializer {
-                    return Ok(self.closure.borrow().get_at(0, "this").unwrap());
+                    return Ok(Self::this(closure));
                 }
                 Ok(Object::Nil)
             }
             Err(e) => {
                 match e {
                     Error::Return { value } => if self.is_initializer {
-                        Ok(self.closure.borrow().get_at(0, "this").unwrap())
+                        Ok(Self::this(closure))
                     } else {
                         Ok(value)
                     }
@@ -94,11 +171,80 @@ impl CallableTrait for Function {
         }
     }
 
+    pub fn bind(&self, instance: &Object) -> Object {
+        let code = match &self.code {
+            Code::Tree { body, closure } => {
+                // `this` is the only slot of the bound scope.
+                let mut e = Environment::new(closure.clone());
+                e.define(instance.clone());
+                let e = Rc::new(RefCell::new(e));
+                Environment::track(&e);
+                Code::Tree {
+                    body: Rc::clone(body),
+                    closure: Some(e),
+                }
+            }
+            Code::Compiled { closure, .. } => Code::Compiled {
+                closure: Rc::clone(closure),
+                this: Some(instance.clone()),
+            },
+        };
+        Object::Function(Self::track(Self {
+            name: self.name.clone(),
+            is_initializer: self.is_initializer,
+            params: Rc::clone(&self.params),
+            code,
+            owner: self.owner,
+       }))
+    }
+}
+
+impl Trace for Function {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        match &self.code {
+            Code::Tree { closure, .. } => {
+                if let Some(closure) = closure {
+                    refs.push(gc::address(closure));
+                }
+            }
+            Code::Compiled { closure, this } => {
+                refs.push(gc::address(closure));
+                if let Some(this) = this {
+                    gc::object_refs(this, refs);
+                }
+            }
+        }
+        true
+    }
+}
+
+impl CallableTrait for Function {
+
+    fn call(&self, interpreter: &Interpreter, arguments: &[Object], _class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
+        interpreter.enter_call()?;
+        let result = if let Code::Compiled { closure, this } = &self.code {
+            vm::call(interpreter, closure, this.as_ref(), self.owner, arguments)
+        } else {
+            let enclosing_class = interpreter.set_current_class(self.owner);
+            let mut result = self.run(interpreter, arguments);
+            // Tail calls come back unmade and run here, one after another,
+            // instead of each nesting inside the last.
+            while let Err(Error::TailCall { function, arguments }) = result {
+                interpreter.set_current_class(function.owner);
+                result = function.run(interpreter, &arguments);
+            }
+            interpreter.set_current_class(enclosing_class);
+            result
+        };
+        interpreter.exit_call();
+        result
+    }
+
     fn arity(&self) -> usize {
         self.params.len()
     }
 
     fn stringify(&self) -> String {
-        self.name.lexeme.clone()
+        self.name.lexeme.to_string()
     }
 }
@@ -0,0 +1,362 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::map::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("gc", 0, native_gc),
+        NativeFunction::new("gcStats", 0, native_gc_stats),
+    ]
+}
+
+/// A value that can sit on a reference cycle: lists, maps, instances,
+/// classes, traits, functions, captured environments and VM closures.
+pub trait Trace {
+    /// Adds the address of every value this one holds an `Rc` to, once per
+    /// `Rc`. Returns false if the value is borrowed and can't be looked at,
+    /// in which case it is kept along with everything it refers to.
+    fn trace(&self, refs: &mut Vec<usize>) -> bool;
+
+    /// Drops everything this value holds. Only called on garbage, and only
+    /// needs doing for values that can be mutated after they are made,
+    /// since every cycle has to pass through one of those.
+    fn clear(&self) {}
+
+    /// Whether a program can see this value, rather than it being one of
+    /// a backend's scopes, closures or upvalues. Only these are counted, so
+    /// that the counts don't depend on the backend's own bookkeeping.
+    fn is_value(&self) -> bool {
+        true
+    }
+}
+
+/// Objects reach each other through `Rc`, which frees acyclic garbage as
+/// soon as it is dropped but leaks cycles. The heap keeps a weak reference
+/// to everything that could be part of a cycle and, every so often, looks
+/// for groups of them that are only referenced from inside the group.
+struct Heap {
+    tracked: RefCell<Vec<Weak<dyn Trace>>>,
+    allocations: Cell<usize>,
+    threshold: Cell<usize>,
+    collections: Cell<usize>,
+    collected: Cell<usize>,
+}
+
+// Tracked allocations between automatic collections, at least.
+const THRESHOLD: usize = 10_000;
+
+thread_local! {
+    static HEAP: Heap = Heap {
+        tracked: RefCell::new(Vec::new()),
+        allocations: Cell::new(0),
+        threshold: Cell::new(THRESHOLD),
+        collections: Cell::new(0),
+        collected: Cell::new(0),
+    };
+}
+
+/// Hands a new value to the collector. May run a collection first.
+pub fn track<T: Trace + 'static>(value: &Rc<T>) {
+    let weak: Weak<dyn Trace> = Rc::downgrade(value) as Weak<dyn Trace>;
+    let due = HEAP.with(|heap| {
+        heap.tracked.borrow_mut().push(weak);
+        heap.allocations.set(heap.allocations.get() + 1);
+        heap.allocations.get() >= heap.threshold.get()
+    });
+    if due {
+        collect();
+    }
+}
+
+/// Frees every unreachable cycle and returns how many values it held, not
+/// counting the backend's own bookkeeping.
+///
+/// This is trial deletion: each value's strong count, less the references
+/// other tracked values hold to it, is the number of references from
+/// outside the heap, such as the interpreter's stacks and scopes. Values
+/// with outside references, and everything reachable from them, are live.
+/// The rest are cleared, which breaks their cycles and lets `Rc` free them.
+pub fn collect() -> usize {
+    let values: Vec<Rc<dyn Trace>> = HEAP.with(|heap| {
+        heap.tracked
+            .take()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect()
+    });
+
+    let index: HashMap<usize, usize> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (address(value), i))
+        .collect();
+
+    // Less one for the `Rc` in `values`.
+    let mut outside: Vec<usize> = values.iter().map(|value| Rc::strong_count(value) - 1).collect();
+    let mut edges = Vec::with_capacity(values.len());
+    let mut roots = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        let mut refs = Vec::new();
+        if !value.trace(&mut refs) {
+            roots.push(i);
+        }
+        let targets: Vec<usize> = refs.iter().filter_map(|r| index.get(r).copied()).collect();
+        for &target in targets.iter() {
+            // Tracing more references than a value's strong count is a bug
+            // in a `Trace` impl. Keeping the value is the safe way out.
+            debug_assert!(outside[target] > 0, "traced more references than a value has");
+            match outside[target].checked_sub(1) {
+                Some(count) => outside[target] = count,
+                None => roots.push(target),
+            }
+        }
+        edges.push(targets);
+    }
+    roots.extend((0..values.len()).filter(|&i| outside[i] > 0));
+
+    let mut live = vec![false; values.len()];
+    while let Some(i) = roots.pop() {
+        if !live[i] {
+            live[i] = true;
+            roots.extend(edges[i].iter().copied().filter(|&target| !live[target]));
+        }
+    }
+
+    let mut garbage = 0;
+    let mut freed = 0;
+    for (value, &live) in values.iter().zip(live.iter()) {
+        if !live {
+            value.clear();
+            garbage += 1;
+            freed += usize::from(value.is_value());
+        }
+    }
+
+    HEAP.with(|heap| {
+        let mut tracked = heap.tracked.borrow_mut();
+        let survivors = values.len() - garbage;
+        tracked.extend(
+            values
+                .iter()
+                .zip(live.iter())
+                .filter(|(_, &live)| live)
+                .map(|(value, _)| Rc::downgrade(value)),
+        );
+        // Scale with the heap so collection stays cheap per allocation.
+        heap.threshold.set(THRESHOLD.max(survivors));
+        heap.allocations.set(0);
+        heap.collections.set(heap.collections.get() + 1);
+        heap.collected.set(heap.collected.get() + freed);
+    });
+
+    freed
+}
+
+/// Identifies an `Rc`'s value, whatever its type.
+pub fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+/// Adds `object`'s address if it is a kind the heap tracks.
+pub fn object_refs(object: &Object, refs: &mut Vec<usize>) {
+    match object {
+        Object::List(list) => refs.push(address(list)),
+        Object::Map(map) => refs.push(address(map)),
+        Object::Function(function) => refs.push(address(function)),
+        Object::Class(class) => refs.push(address(class)),
+        Object::Instance(instance) => refs.push(address(instance)),
+        Object::Trait(t) => refs.push(address(t)),
+        _ => {}
+    }
+}
+
+impl Trace for RefCell<Vec<Object>> {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        let Ok(elements) = self.try_borrow_mut() else {
+            return false;
+        };
+        elements.iter().for_each(|element| object_refs(element, refs));
+        true
+    }
+
+    fn clear(&self) {
+        self.take();
+    }
+}
+
+fn native_gc(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Num(collect() as f64))
+}
+
+fn native_gc_stats(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    let mut stats = MapStruct::new();
+    HEAP.with(|heap| {
+        let tracked = heap
+            .tracked
+            .borrow()
+            .iter()
+            .filter(|value| value.upgrade().is_some_and(|value| value.is_value()))
+            .count();
+        for (key, value) in [
+            ("tracked", tracked),
+            ("collections", heap.collections.get()),
+            ("collected", heap.collected.get()),
+            ("threshold", heap.threshold.get()),
+        ] {
+            stats.insert(key.into(), Object::Num(value as f64));
+        }
+    });
+    Ok(Object::map(stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{prepare, run, Backend};
+
+    // Each test runs on a thread of its own, so it has the heap to itself.
+
+    fn push(list: &Object, value: Object) {
+        let Object::List(elements) = list else {
+            panic!("not a list");
+        };
+        elements.borrow_mut().push(value);
+    }
+
+    // Runs a program, then collects. Globals the program leaves stay live.
+    fn collect_after(source: &str, backend: Backend) -> (Interpreter, usize) {
+        let interpreter = Interpreter::new();
+        let program = prepare(source).unwrap();
+        assert!(run(&interpreter, backend, &program).is_ok());
+        let freed = collect();
+        (interpreter, freed)
+    }
+
+    #[test]
+    fn frees_a_list_cycle() {
+        let list = Object::list(Vec::new());
+        push(&list, list.clone());
+        drop(list);
+        assert_eq!(collect(), 1);
+        assert_eq!(collect(), 0);
+    }
+
+    #[test]
+    fn frees_a_map_cycle_through_a_list() {
+        let list = Object::list(Vec::new());
+        let map = Object::map(MapStruct::new());
+        if let Object::Map(entries) = &map {
+            entries.borrow_mut().insert("list".into(), list.clone());
+        }
+        push(&list, map);
+        drop(list);
+        assert_eq!(collect(), 2);
+    }
+
+    #[test]
+    fn keeps_a_cycle_that_is_still_referenced() {
+        let list = Object::list(Vec::new());
+        push(&list, list.clone());
+        push(&list, Object::list(vec![Object::Num(1.0)]));
+        assert_eq!(collect(), 0);
+        let Object::List(elements) = &list else {
+            unreachable!()
+        };
+        assert_eq!(elements.borrow().len(), 2);
+        assert_eq!(elements.borrow()[1].to_string(), "[1]");
+        drop(list);
+        assert_eq!(collect(), 2);
+    }
+
+    #[test]
+    fn keeps_a_cycle_that_is_borrowed() {
+        let list = Object::list(Vec::new());
+        push(&list, list.clone());
+        let Object::List(elements) = list.clone() else {
+            unreachable!()
+        };
+        drop(list);
+        let borrowed = elements.borrow();
+        assert_eq!(collect(), 0);
+        drop(borrowed);
+        drop(elements);
+        assert_eq!(collect(), 1);
+    }
+
+    #[test]
+    fn leaves_acyclic_garbage_to_reference_counting() {
+        let list = Object::list(vec![Object::list(Vec::new())]);
+        drop(list);
+        assert_eq!(collect(), 0);
+    }
+
+    #[test]
+    fn frees_instance_cycles() {
+        let source = "
+            class Pair { init() { this.other = nil; } }
+            fn make() {
+                var a = Pair();
+                var b = Pair();
+                a.other = b;
+                b.other = a;
+            }
+            make();
+        ";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (_interpreter, freed) = collect_after(source, backend);
+            assert_eq!(freed, 2);
+        }
+    }
+
+    #[test]
+    fn frees_an_instance_holding_its_bound_method() {
+        let source = "
+            class A {
+                init() { this.m = this.go; }
+                go() {}
+            }
+            A();
+        ";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (_interpreter, freed) = collect_after(source, backend);
+            assert_eq!(freed, 2, "the instance and the bound method");
+        }
+    }
+
+    #[test]
+    fn frees_a_closure_that_refers_to_itself() {
+        let source = "
+            fn make() {
+                fn again() { return again; }
+                return nil;
+            }
+            make();
+        ";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (_interpreter, freed) = collect_after(source, backend);
+            assert_eq!(freed, 1);
+        }
+    }
+
+    #[test]
+    fn keeps_cycles_reachable_from_globals() {
+        let source = "
+            class Node { init() { this.next = this; } }
+            var kept = Node();
+            var list = [];
+            push(list, list);
+        ";
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let (interpreter, freed) = collect_after(source, backend);
+            assert_eq!(freed, 0);
+            let program = prepare("kept = nil; list = nil;").unwrap();
+            assert!(run(&interpreter, backend, &program).is_ok());
+            assert_eq!(collect(), 2);
+        }
+    }
+}
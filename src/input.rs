@@ -0,0 +1,60 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::io::{self, BufRead};
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("input", 1, native_input),
+        NativeFunction::new("readLine", 0, native_read_line),
+        NativeFunction::new("inputLines", 0, native_input_lines),
+    ]
+}
+
+/// Reads one line from stdin without its line ending, or `None` at EOF.
+fn read_line() -> Result<Option<String>, Error> {
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Some(line))
+        }
+        Err(e) => Err(Error::call_error(&format!("Failed to read stdin: {e}."))),
+    }
+}
+
+fn line_object(line: Option<String>) -> Object {
+    match line {
+        Some(line) => Object::Str(line.into()),
+        None => Object::Nil,
+    }
+}
+
+fn native_input(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    terp.write_output(&args[0].to_string())?;
+    terp.flush_output()
+        .map_err(|e| Error::call_error(&format!("Failed to write prompt: {e}.")))?;
+    Ok(line_object(read_line()?))
+}
+
+fn native_read_line(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    Ok(line_object(read_line()?))
+}
+
+/// Reads everything left on stdin before returning, so the whole input is
+/// held in memory and counts against `--max-heap`. It never returns on an
+/// endless pipe; a `readLine()` loop is the way to stream input.
+fn native_input_lines(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    let mut lines = Vec::new();
+    while let Some(line) = read_line()? {
+        lines.push(Object::Str(line.into()));
+    }
+    Ok(Object::list(lines))
+}
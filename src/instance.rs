@@ -1,28 +1,44 @@
+use crate::callable::*;
 use crate::errors::*;
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
+use crate::interpreter::Interpreter;
 use crate::tokens::*;
 use std::cell::RefCell;
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct InstanceStruct {
     pub class: Rc<ClassStruct>,
-    fields: RefCell<HashMap<String, Object>>,
+    fields: RefCell<HashMap<RcStr, Object>>,
+    private_fields: RefCell<HashMap<(usize, RcStr), Object>>,
 }
 
 impl InstanceStruct {
-    pub fn new(class: Rc<ClassStruct>) -> Self {
-        InstanceStruct {
+    pub fn new(class: Rc<ClassStruct>) -> Rc<Self> {
+        let instance = Rc::new(InstanceStruct {
             class: Rc::clone(&class),
             fields: RefCell::new(HashMap::new()),
-        }
+            private_fields: RefCell::new(HashMap::new()),
+        });
+        gc::track(&instance);
+        instance
     }
 
-    pub fn get(&self, name: &Token, this: &Rc<InstanceStruct>) -> Result<Object, Error> {
-        if let Entry::Occupied(o) = self.fields.borrow_mut().entry(name.lexeme.clone()) {
-            Ok(o.get().clone())
-        } else if let Some(method) = self.class.find_method(name.lexeme.clone()) {
+    pub fn get(&self, name: &Token, this: &Rc<InstanceStruct>, interpreter: &Interpreter) -> Result<Object, Error> {
+        if name.is_private() {
+            return self.get_private(name, this, interpreter);
+        }
+
+        if let Some(getter) = self.class.find_getter(&name.lexeme) {
+            return Self::call_accessor(getter, name, this, interpreter, &[]);
+        }
+
+        let field = self.fields.borrow().get(&name.lexeme).cloned();
+        if let Some(field) = field {
+            Ok(field)
+        } else if let Some(method) = self.class.find_method(&name.lexeme) {
             if let Object::Function(function) = method {
                 Ok(function.bind(&Object::Instance(Rc::clone(this))))
             } else {
@@ -36,7 +52,135 @@ impl InstanceStruct {
         }
     }
 
-    pub fn set(&self, name: &Token, value: Object) {
-        self.fields.borrow_mut().insert(name.lexeme.clone(), value);
+    /// Whether a public property lookup for `name` would succeed.
+    pub fn has(&self, name: &str) -> bool {
+        self.class.find_getter(name).is_some()
+            || self.fields.borrow().contains_key(name)
+            || self.class.find_method(name).is_some()
+    }
+
+    /// Names of the public fields set on this instance. Declared fields come
+    /// first in declaration order, followed by the rest sorted by name.
+    pub fn field_names(&self) -> Vec<String> {
+        let fields = self.fields.borrow();
+        let mut names: Vec<String> = self
+            .class
+            .field_names()
+            .into_iter()
+            .filter(|name| fields.contains_key(name.as_str()))
+            .collect();
+        let mut rest: Vec<String> = fields
+            .keys()
+            .filter(|name| !names.iter().any(|own| own == &***name))
+            .map(|name| name.to_string())
+            .collect();
+        rest.sort();
+        names.append(&mut rest);
+        names
+    }
+
+    pub fn set(&self, name: &Token, value: Object, this: &Rc<InstanceStruct>, interpreter: &Interpreter) -> Result<(), Error> {
+        if name.is_private() {
+            return self.set_private(name, value, this, interpreter);
+        }
+
+        if let Some(setter) = self.class.find_setter(&name.lexeme) {
+            Self::call_accessor(setter, name, this, interpreter, &[value])?;
+        } else if self.class.find_getter(&name.lexeme).is_some() {
+            return Err(Error::runtime_error(
+                name,
+                &format!("Cannot assign to read-only property '{}'.", name.lexeme),
+            ));
+        } else {
+            self.fields.borrow_mut().insert(name.lexeme.clone(), value);
+        }
+        Ok(())
+    }
+
+    fn get_private(&self, name: &Token, this: &Rc<InstanceStruct>, interpreter: &Interpreter) -> Result<Object, Error> {
+        let class = self.private_scope(name, interpreter)?;
+
+        if let Some(getter) = class.own_getter(&name.lexeme) {
+            return Self::call_accessor(getter, name, this, interpreter, &[]);
+        }
+
+        let key = (class.id, name.lexeme.clone());
+        if let Some(value) = self.private_fields.borrow().get(&key) {
+            Ok(value.clone())
+        } else if let Some(Object::Function(method)) = class.own_method(&name.lexeme) {
+            Ok(method.bind(&Object::Instance(Rc::clone(this))))
+        } else {
+            Err(Error::runtime_error(
+                name,
+                &format!("Undefined property '{}'.", name.lexeme),
+            ))
+        }
+    }
+
+    fn set_private(&self, name: &Token, value: Object, this: &Rc<InstanceStruct>, interpreter: &Interpreter) -> Result<(), Error> {
+        let class = self.private_scope(name, interpreter)?;
+
+        if let Some(setter) = class.own_setter(&name.lexeme) {
+            Self::call_accessor(setter, name, this, interpreter, &[value])?;
+        } else if class.own_getter(&name.lexeme).is_some() {
+            return Err(Error::runtime_error(
+                name,
+                &format!("Cannot assign to read-only property '{}'.", name.lexeme),
+            ));
+        } else {
+            self.private_fields
+                .borrow_mut()
+                .insert((class.id, name.lexeme.clone()), value);
+        }
+        Ok(())
+    }
+
+    // Private members belong to the class whose code is running, and only
+    // when `this` is an instance of that class.
+    fn private_scope(&self, name: &Token, interpreter: &Interpreter) -> Result<&ClassStruct, Error> {
+        interpreter
+            .current_class()
+            .and_then(|id| self.class.find_class(id))
+            .ok_or_else(|| {
+                Error::runtime_error(
+                    name,
+                    &format!("Cannot access private member '{}' from outside its class.", name.lexeme),
+                )
+            })
+    }
+
+    fn call_accessor(accessor: Object, name: &Token, this: &Rc<InstanceStruct>, interpreter: &Interpreter, arguments: &[Object]) -> Result<Object, Error> {
+        if let Object::Function(function) = accessor {
+            if let Object::Function(bound) = function.bind(&Object::Instance(Rc::clone(this))) {
+                // Errors without a token of their own, such as going past
+                // the recursion limit, are reported at the property.
+                return bound.call(interpreter, arguments, None).map_err(|e| match e {
+                    Error::CallError { message } => Error::runtime_error(name, &message),
+                    e => e,
+                });
+            }
+        }
+        Err(Error::runtime_error(
+            name,
+            &format!("Accessor '{}' is not a function.", name.lexeme),
+        ))
+    }
+}
+
+impl Trace for InstanceStruct {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        let (Ok(fields), Ok(private_fields)) = (self.fields.try_borrow_mut(), self.private_fields.try_borrow_mut()) else {
+            return false;
+        };
+        refs.push(gc::address(&self.class));
+        for value in fields.values().chain(private_fields.values()) {
+            gc::object_refs(value, refs);
+        }
+        true
+    }
+
+    fn clear(&self) {
+        self.fields.take();
+        self.private_fields.take();
     }
 }
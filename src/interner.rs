@@ -0,0 +1,180 @@
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// An immutable, reference-counted string. Copying one only bumps a
+/// reference count.
+///
+/// Identifiers and string literals are interned: there is one allocation
+/// per distinct text, so two interned strings are equal exactly when they
+/// share it. Strings built at runtime are not interned and are compared by
+/// their text.
+#[derive(Clone)]
+pub struct RcStr {
+    text: Rc<str>,
+    interned: bool,
+}
+
+thread_local! {
+    static INTERNED: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// The interned string for `text`. Interned strings live as long as the
+/// interpreter, so this is for names and source text, not runtime data.
+pub fn intern(text: &str) -> RcStr {
+    INTERNED.with(|interned| {
+        let mut interned = interned.borrow_mut();
+        let text = match interned.get(text) {
+            Some(text) => Rc::clone(text),
+            None => {
+                let text: Rc<str> = Rc::from(text);
+                interned.insert(Rc::clone(&text));
+                text
+            }
+        };
+        RcStr { text, interned: true }
+    })
+}
+
+impl PartialEq for RcStr {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.text, &other.text)
+            || (!(self.interned && other.interned) && self.text == other.text)
+    }
+}
+
+impl Eq for RcStr {}
+
+impl PartialEq<str> for RcStr {
+    fn eq(&self, other: &str) -> bool {
+        &*self.text == other
+    }
+}
+
+impl PartialEq<&str> for RcStr {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.text == *other
+    }
+}
+
+impl PartialOrd for RcStr {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RcStr {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.text.cmp(&other.text)
+    }
+}
+
+// Hashes the text, like `str`, so maps keyed by `RcStr` can be searched
+// with a `&str`.
+impl Hash for RcStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state)
+    }
+}
+
+impl Deref for RcStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Borrow<str> for RcStr {
+    fn borrow(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<&str> for RcStr {
+    fn from(text: &str) -> Self {
+        RcStr { text: Rc::from(text), interned: false }
+    }
+}
+
+impl From<String> for RcStr {
+    fn from(text: String) -> Self {
+        RcStr { text: Rc::from(text), interned: false }
+    }
+}
+
+impl Default for RcStr {
+    fn default() -> Self {
+        intern("")
+    }
+}
+
+impl fmt::Display for RcStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&*self.text, f)
+    }
+}
+
+impl fmt::Debug for RcStr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.text, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn interning_shares_one_allocation() {
+        let a = intern("name");
+        let b = intern(&(String::from("na") + "me"));
+        assert!(Rc::ptr_eq(&a.text, &b.text));
+        assert_eq!(a, b);
+        assert_ne!(a, intern("other"));
+    }
+
+    #[test]
+    fn clones_share_the_text() {
+        let a = RcStr::from("built at runtime");
+        let b = a.clone();
+        assert!(Rc::ptr_eq(&a.text, &b.text));
+    }
+
+    #[test]
+    fn runtime_strings_compare_by_text() {
+        let interned = intern("key");
+        let built = RcStr::from(String::from("k") + "ey");
+        assert!(!built.interned);
+        assert_eq!(interned, built);
+        assert_eq!(built, interned);
+        assert_eq!(built, RcStr::from("key"));
+        assert_ne!(built, RcStr::from("keys"));
+        assert_eq!(built, "key");
+    }
+
+    #[test]
+    fn hashes_like_str() {
+        let mut map = HashMap::new();
+        map.insert(intern("key"), 1);
+        assert_eq!(map.get("key"), Some(&1));
+        assert_eq!(map.get(&RcStr::from("key")), Some(&1));
+        map.insert(RcStr::from("key"), 2);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[&intern("key")], 2);
+    }
+
+    #[test]
+    fn orders_by_text() {
+        let mut strings = vec![RcStr::from("b"), intern("c"), intern("a")];
+        strings.sort();
+        assert_eq!(strings, [intern("a"), intern("b"), intern("c")]);
+        assert_eq!(RcStr::default(), "");
+    }
+}
@@ -1,20 +1,53 @@
 use crate::callable::*;
-use crate::enviroment::Environment;
+use crate::collections;
+use crate::datetime;
+use crate::enviroment::{Environment, Globals, Slot};
 use crate::errors::*;
 use crate::expr::*;
 use crate::functions::*;
+use crate::gc;
+use crate::input;
+use crate::interner::{intern, RcStr};
 use crate::native_functions::*;
+use crate::files;
+use crate::json;
+use crate::map::*;
+use crate::math;
+use crate::process;
+use crate::random::{self, Rng};
+use crate::reflection;
+use crate::regex;
+use crate::sandbox::{Capability, Config, Meter};
+use crate::strings;
 use crate::stmt::*;
 use crate::tokens::*;
+use crate::traits::*;
+use crate::vm::Vm;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell, RefMut};
 use std::collections::HashMap;
+use std::io::{self, Write};
 use std::ops::Deref;
 use std::rc::Rc;
+
+/// How many calls into arc code may be running at once, unless
+/// `Config::recursion_limit` says otherwise. The host stack has to be deep
+/// enough for this many; see `STACK_SIZE` in main.rs.
+pub const RECURSION_LIMIT: usize = 10_000;
+
 pub struct Interpreter {
-    pub globals: Rc<RefCell<Environment>>,
-    environment: RefCell<Rc<RefCell<Environment>>>,
-    locals: RefCell<HashMap<Rc<Expr>, usize>>,
+    pub globals: RefCell<Globals>,
+    // None while running top-level code.
+    environment: RefCell<Option<Rc<RefCell<Environment>>>>,
+    current_class: Cell<Option<usize>>,
+    next_class_id: Cell<usize>,
+    // Calls into arc code that haven't returned yet, and how many may be.
+    call_depth: Cell<usize>,
+    recursion_limit: usize,
+    meter: Meter,
+    output: RefCell<Box<dyn Write>>,
+    rng: RefCell<Rng>,
+    vm: Vm,
 }
 
 impl StmtVisitor<()> for Interpreter {
@@ -33,28 +66,35 @@ impl StmtVisitor<()> for Interpreter {
             None
         };
 
-        self.environment
-            .borrow()
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), Object::Nil);
+        let traits = self.evaluate_names(&stmt.traits, "Can only mix in traits.", |o| match o {
+            Object::Trait(t) => Some(t),
+            _ => None,
+        })?;
+        let interfaces = self.evaluate_names(&stmt.interfaces, "Can only implement interfaces.", |o| match o {
+            Object::Interface(i) => Some(i),
+            _ => None,
+        })?;
 
         let enclosing = if let Some(ref s) = superclass {
-            let mut e = Environment::new_with_enclosing(self.environment.borrow().clone());
-            e.define("super".to_string(), Object::Class(s.clone()));
-            Some(self.environment.replace(Rc::new(RefCell::new(e))))
+            let mut e = Environment::new(self.environment.borrow().clone());
+            e.define(Object::Class(s.clone()));
+            Some(self.environment.replace(Some(Rc::new(RefCell::new(e)))))
         } else {
             None
         };
 
+        let id = self.new_class_id();
+
         let mut methods = HashMap::new();
         for method in stmt.methods.deref() {
             if let Stmt::Function(func) = method.deref() {
                 let is_initializer = func.name.lexeme == "init";
-                let function = Object::Function(Rc::new(Function::new(
+                let function = Object::Function(Function::new(
                     func,
                     self.environment.borrow().deref(),
                     is_initializer,
-                )));
+                    Some(id),
+                ));
                 methods.insert(func.name.lexeme.clone(), function);
             } else {
                 return Err(Error::runtime_error(
@@ -64,20 +104,80 @@ impl StmtVisitor<()> for Interpreter {
             };
         }
 
-        let cls = Object::Class(Rc::new(ClassStruct::new(
-            stmt.name.lexeme.clone(),
-            superclass,
-            methods,
-        )));
+        let getters = self.accessors(&stmt.getters, id);
+        let setters = self.accessors(&stmt.setters, id);
+
+        let mut fields = Vec::new();
+        for field in stmt.fields.iter() {
+            if let Stmt::Expression(e) = field.deref() {
+                if let Expr::Set(set) = e.expression.deref() {
+                    fields.push(set.name.lexeme.to_string());
+                }
+            }
+        }
+        let field_initializer = if stmt.fields.is_empty() {
+            None
+        } else {
+            let declaration = FunctionStmt {
+                name: stmt.name.clone(),
+                params: Rc::new(Vec::new()),
+                body: Rc::clone(&stmt.fields),
+            };
+            Some(Function::new(
+                &declaration,
+                self.environment.borrow().deref(),
+                false,
+                Some(id),
+            ))
+        };
 
         if let Some(previous) = enclosing {
             self.environment.replace(previous);
         }
 
-        self.environment
-            .borrow()
-            .borrow_mut()
-            .assign(&stmt.name, cls.clone())?;
+        let members = ClassMembers {
+            methods,
+            getters,
+            setters,
+            abstract_methods: Self::signatures(&stmt.abstract_methods),
+            fields,
+            field_initializer,
+        };
+
+        let cls = Object::Class(self.build_class(&stmt.name, id, superclass, traits, interfaces, members)?);
+        self.define(&stmt.name, cls);
+        Ok(())
+    }
+
+    fn visit_trait_stmt(&self, _: Rc<Stmt>, stmt: &TraitStmt) -> Result<(), Error> {
+        let mut methods = HashMap::new();
+        for method in stmt.methods.deref() {
+            if let Stmt::Function(func) = method.deref() {
+                let function = Object::Function(Function::new(
+                    func,
+                    self.environment.borrow().deref(),
+                    false,
+                    None,
+                ));
+                methods.insert(func.name.lexeme.clone(), function);
+            }
+        }
+
+        let t = Object::Trait(TraitStruct::new(
+            stmt.name.lexeme.to_string(),
+            methods,
+            Self::signatures(&stmt.required),
+        ));
+        self.define(&stmt.name, t);
+        Ok(())
+    }
+
+    fn visit_interface_stmt(&self, _: Rc<Stmt>, stmt: &InterfaceStmt) -> Result<(), Error> {
+        let interface = Object::Interface(Rc::new(InterfaceStruct::new(
+            stmt.name.lexeme.to_string(),
+            Self::signatures(&stmt.methods),
+        )));
+        self.define(&stmt.name, interface);
         Ok(())
     }
 
@@ -90,11 +190,13 @@ impl StmtVisitor<()> for Interpreter {
         }
     }
     fn visit_function_stmt(&self, _: Rc<Stmt>, stmt: &FunctionStmt) -> Result<(), Error> {
-        let function = Function::new(stmt, self.environment.borrow().deref(), false);
-        self.environment.borrow().borrow_mut().define(
-            stmt.name.lexeme.clone(),
-            Object::Function(Rc::new(function)),
+        let function = Function::new(
+            stmt,
+            self.environment.borrow().deref(),
+            false,
+            self.current_class.get(),
         );
+        self.define(&stmt.name, Object::Function(function));
         Ok(())
     }
 
@@ -107,14 +209,12 @@ impl StmtVisitor<()> for Interpreter {
         Ok(())
     }
     fn visit_block_stmt(&self, _: Rc<Stmt>, stmt: &BlockStmt) -> Result<(), Error> {
-        let environment =
-            Environment::new_with_enclosing(Rc::clone(&self.environment.borrow().clone()));
+        let environment = Environment::new(self.environment.borrow().clone());
         self.execute_block(&stmt.statements, environment)
     }
     fn visit_print_stmt(&self, _: Rc<Stmt>, stmt: &PrintStmt) -> Result<(), Error> {
         let value = self.evaluate(stmt.expression.clone())?;
-        println!("{}", value);
-        Ok(())
+        self.write_output(&format!("{}\n", value))
     }
 
     fn visit_expression_stmt(&self, _: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<(), Error> {
@@ -129,10 +229,7 @@ impl StmtVisitor<()> for Interpreter {
             Object::Nil
         };
 
-        self.environment
-            .borrow()
-            .borrow_mut()
-            .define(stmt.name.lexeme.clone(), value);
+        self.define(&stmt.name, value);
         Ok(())
     }
 
@@ -145,56 +242,33 @@ impl StmtVisitor<()> for Interpreter {
 }
 
 impl ExprVisitor<Object> for Interpreter {
-    fn visit_super_expr(&self, wrapper: Rc<Expr>, expr: &SuperExpr) -> Result<Object, Error> {
-        let distance = *self.locals.borrow().get(&wrapper).unwrap();
-        let superclass = if let Some(sc) = self
-            .environment
-            .borrow()
-            .borrow()
-            .get_at(distance, "super")
-            .ok()
-        {
-            if let Object::Class(superclass) = sc {
-                superclass
-            } else {
-                panic!("Unable to extract superclass");
-            }
+    fn visit_super_expr(&self, _: Rc<Expr>, expr: &SuperExpr) -> Result<Object, Error> {
+        let slot = expr.slot.get().unwrap();
+        let superclass = if let Ok(Object::Class(superclass)) = self.look_up_variable(&expr.keyword, Some(slot)) {
+            superclass
         } else {
             panic!("Unable to extract superclass");
         };
 
-        let object = self
-            .environment
-            .borrow()
-            .borrow()
-            .get_at(distance - 1, "this")
-            .ok()
-            .unwrap();
+        // `this` is bound in the scope just inside the one holding `super`.
+        let object = self.look_up_variable(
+            &expr.keyword,
+            Some(Slot { depth: slot.depth - 1, index: 0 }),
+        )?;
 
-        if let Some(method) = superclass.find_method(expr.method.lexeme.clone()) {
-            if let Object::Function(func) = method {
-                Ok(func.bind(&object))
-            } else {
-                panic!("method was not a function");
-            }
-        } else {
-            Err(Error::runtime_error(
-                &expr.method,
-                &format!("Undefined property '{}'.", expr.method.lexeme),
-            ))
-        }
+        self.get_super(&superclass, &object, &expr.method)
     }
 
-    fn visit_this_expr(&self, wrapper: Rc<Expr>, expr: &ThisExpr) -> Result<Object, Error> {
-        self.look_up_variable(&expr.keyword, wrapper)
+    fn visit_this_expr(&self, _: Rc<Expr>, expr: &ThisExpr) -> Result<Object, Error> {
+        self.look_up_variable(&expr.keyword, expr.slot.get())
     }
 
     fn visit_set_expr(&self, _wrapper: Rc<Expr>, expr: &SetExpr) -> Result<Object, Error> {
         let object = self.evaluate(expr.object.clone())?;
 
-        if let Object::Instance(inst) = object {
+        if let Object::Instance(_) = object {
             let value = self.evaluate(expr.value.clone())?;
-            inst.set(&expr.name, value.clone());
+            self.set_property(object, &expr.name, value.clone())?;
             return Ok(value);
         }
 
@@ -206,16 +280,40 @@ impl ExprVisitor<Object> for Interpreter {
 
     fn visit_get_expr(&self, _wrapper: Rc<Expr>, expr: &GetExpr) -> Result<Object, Error> {
         let object = self.evaluate(expr.object.clone())?;
+        self.get_property(object, &expr.name)
+    }
+    fn visit_list_expr(&self, _: Rc<Expr>, expr: &ListExpr) -> Result<Object, Error> {
+        let mut elements = Vec::new();
+        for element in expr.elements.iter() {
+            elements.push(self.evaluate(element.clone())?);
+        }
+        Ok(Object::list(elements))
+    }
 
-        if let Object::Instance(inst) = object {
-            return Ok(inst.get(&expr.name, &inst)?);
-        } else {
-            Err(Error::runtime_error(
-                &expr.name,
-                "Only instances have fields.",
-            ))
+    fn visit_map_expr(&self, _: Rc<Expr>, expr: &MapExpr) -> Result<Object, Error> {
+        let mut map = MapStruct::new();
+        for (key, value) in expr.keys.iter().zip(expr.values.iter()) {
+            let key = self.evaluate(key.clone())?;
+            let key = self.map_key(&expr.brace, &key)?.clone();
+            let value = self.evaluate(value.clone())?;
+            map.insert(key, value);
         }
+        Ok(Object::map(map))
+    }
+
+    fn visit_index_expr(&self, _: Rc<Expr>, expr: &IndexExpr) -> Result<Object, Error> {
+        let object = self.evaluate(expr.object.clone())?;
+        let index = self.evaluate(expr.index.clone())?;
+        self.index(&expr.bracket, object, index)
     }
+
+    fn visit_setindex_expr(&self, _: Rc<Expr>, expr: &SetIndexExpr) -> Result<Object, Error> {
+        let object = self.evaluate(expr.object.clone())?;
+        let index = self.evaluate(expr.index.clone())?;
+        let value = self.evaluate(expr.value.clone())?;
+        self.set_index(&expr.bracket, object, index, value)
+    }
+
     fn visit_logical_expr(&self, _: Rc<Expr>, expr: &LogicalExpr) -> Result<Object, Error> {
         let left = self.evaluate(expr.left.clone())?;
 
@@ -231,14 +329,12 @@ impl ExprVisitor<Object> for Interpreter {
 
         self.evaluate(expr.right.clone())
     }
-    fn visit_assign_expr(&self, wrapper: Rc<Expr>, expr: &AssignExpr) -> Result<Object, Error> {
+    fn visit_assign_expr(&self, _: Rc<Expr>, expr: &AssignExpr) -> Result<Object, Error> {
         let value = self.evaluate(expr.value.clone())?;
-        if let Some(distance) = self.locals.borrow().get(&wrapper) {
-            self.environment.borrow().borrow_mut().assign_at(
-                *distance,
-                &expr.name,
-                value.clone(),
-            )?;
+        if let Some(slot) = expr.slot.get() {
+            self.current_environment()
+                .borrow_mut()
+                .assign_at(slot.depth, slot.index, value.clone());
         } else {
             self.globals
                 .borrow_mut()
@@ -253,7 +349,7 @@ impl ExprVisitor<Object> for Interpreter {
     }
 
     fn visit_grouping_expr(&self, _: Rc<Expr>, expr: &GroupingExpr) -> Result<Object, Error> {
-        Ok(self.evaluate(expr.expression.clone())?)
+        self.evaluate(expr.expression.clone())
     }
 
     fn visit_unary_expr(&self, _: Rc<Expr>, expr: &UnaryExpr) -> Result<Object, Error> {
@@ -267,7 +363,7 @@ impl ExprVisitor<Object> for Interpreter {
                 Ok(Object::Nil)
             }
             TokenKind::Bang => {
-                return Ok(Object::Bool(!self.is_truthy(right)));
+                Ok(Object::Bool(!self.is_truthy(right)))
             }
             _ => Err(Error::runtime_error(
                 &expr.operator,
@@ -279,9 +375,125 @@ impl ExprVisitor<Object> for Interpreter {
     fn visit_binary_expr(&self, _: Rc<Expr>, expr: &BinaryExpr) -> Result<Object, Error> {
         let left = self.evaluate(expr.left.clone())?;
         let right = self.evaluate(expr.right.clone())?;
-        let operator = &expr.operator.kind;
+        self.binary(left, &expr.operator, right)
+    }
+
+    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<Object, Error> {
+        let callee = self.evaluate(expr.callee.clone())?;
+
+        let mut arguments = Vec::new();
+
+        for argument in expr.arguments.clone() {
+            arguments.push(self.evaluate(argument)?);
+        }
+
+        let mut keywords = Vec::new();
+        for (name, value) in expr.keywords.iter().zip(expr.keyword_values.iter()) {
+            keywords.push((name, self.evaluate(value.clone())?));
+        }
+
+        // Unwinding to the function being returned from before making the
+        // call keeps tail recursion from growing the host stack.
+        if expr.tail.get() && keywords.is_empty() {
+            if let Object::Function(function) = &callee {
+                if function.bytecode().is_none() && arguments.len() == function.arity() {
+                    return Err(Error::tail_call(Rc::clone(function), arguments));
+                }
+            }
+        }
+
+        self.call(&callee, arguments, keywords).map_err(|e| match e {
+            Error::CallError { message } => Error::runtime_error(&expr.paren, &message),
+            e => e,
+        })
+    }
+
+    fn visit_variable_expr(&self, _: Rc<Expr>, expr: &VariableExpr) -> Result<Object, Error> {
+        self.look_up_variable(&expr.name, expr.slot.get())
+    }
+}
+
+impl Interpreter {
+    /// Builds an interpreter whose program output goes to stdout.
+    pub fn new() -> Interpreter {
+        Self::with_config(&Config::default())
+    }
+
+    /// Builds an interpreter that holds scripts to `config`'s limits and
+    /// leaves out the natives it doesn't allow.
+    pub fn with_config(config: &Config) -> Interpreter {
+        let mut global = Globals::default();
+        let allowed = |capability, natives: fn() -> Vec<NativeFunction>| {
+            if config.allows(capability) {
+                natives()
+            } else {
+                Vec::new()
+            }
+        };
+
+        global.define(
+            intern("clock"),
+            Object::Native(Rc::new(Native {
+                func: Rc::new(NativeClock {}),
+            })),
+        );
+
+        for native in natives()
+            .into_iter()
+            .chain(reflection::natives())
+            .chain(strings::natives())
+            .chain(allowed(Capability::Files, files::natives))
+            .chain(input::natives())
+            .chain(allowed(Capability::Processes, process::natives))
+            .chain(random::natives())
+            .chain(collections::natives())
+            .chain(gc::natives())
+        {
+            global.define(intern(native.name()), native.into_object());
+        }
+
+        for module in [
+            math::module(),
+            json::module(),
+            regex::module(),
+            datetime::module(),
+        ] {
+            global.define(intern(&module.name), Object::Module(Rc::new(module)));
+        }
 
-        let result = match (left, right) {
+        Interpreter {
+            globals: RefCell::new(global),
+            environment: RefCell::new(None),
+            current_class: Cell::new(None),
+            next_class_id: Cell::new(0),
+            call_depth: Cell::new(0),
+            recursion_limit: config.recursion_limit,
+            meter: Meter::new(config),
+            output: RefCell::new(Box::new(io::stdout())),
+            rng: RefCell::new(Rng::from_time()),
+            vm: Vm::default(),
+        }
+    }
+
+    /// Applies a binary operator. Both backends share this so they agree
+    /// on what each operator accepts.
+    pub fn binary(&self, left: Object, operator: &Token, right: Object) -> Result<Object, Error> {
+        let result = Self::operate(left, &operator.kind, right);
+        if result == Object::ArithmeticError {
+            Err(Error::runtime_error(
+                operator,
+                "Invalid binary operator",
+            ))
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// What a binary operator gives for two values, or `ArithmeticError`
+    /// if it doesn't apply to them. The optimizer uses this to fold
+    /// constants, so it must not have side effects.
+    pub fn operate(left: Object, operator: &TokenKind, right: Object) -> Object {
+        match (left, right) {
             (Object::Num(left), Object::Num(right)) => match operator {
                 TokenKind::Minus => Object::Num(left - right),
                 TokenKind::Slash => {
@@ -303,19 +515,19 @@ impl ExprVisitor<Object> for Interpreter {
             },
 
             (Object::Str(left), Object::Str(right)) => match operator {
-                TokenKind::Plus => Object::Str(format!("{}{}", left, right)),
+                TokenKind::Plus => Object::Str(format!("{}{}", left, right).into()),
                 TokenKind::NotEqual => Object::Bool(left != right),
                 TokenKind::EqualEqual => Object::Bool(left == right),
                 _ => Object::ArithmeticError,
             },
 
             (Object::Str(left), Object::Num(right)) => match operator {
-                TokenKind::Plus => Object::Str(format!("{}{}", left, right)),
+                TokenKind::Plus => Object::Str(format!("{}{}", left, right).into()),
                 _ => Object::ArithmeticError,
             },
 
             (Object::Num(left), Object::Str(right)) => match operator {
-                TokenKind::Plus => Object::Str(format!("{}{}", left, right)),
+                TokenKind::Plus => Object::Str(format!("{}{}", left, right).into()),
                 _ => Object::ArithmeticError,
             },
 
@@ -331,89 +543,323 @@ impl ExprVisitor<Object> for Interpreter {
                 _ => Object::ArithmeticError,
             },
 
-            (Object::Nil, _) => match operator {
+            (Object::Nil, _) | (_, Object::Nil) => match operator {
                 TokenKind::NotEqual => Object::Bool(true),
                 TokenKind::EqualEqual => Object::Bool(false),
                 _ => Object::ArithmeticError,
             },
 
             _ => Object::ArithmeticError,
-        };
+        }
+    }
 
-        if result == Object::ArithmeticError {
+    pub fn get_property(&self, object: Object, name: &Token) -> Result<Object, Error> {
+        if let Object::Instance(inst) = object {
+            inst.get(name, &inst, self)
+        } else if let Object::Module(module) = object {
+            module.get(&name.lexeme).ok_or_else(|| {
+                Error::runtime_error(
+                    name,
+                    &format!("Module '{}' has no member '{}'.", module.name, name.lexeme),
+                )
+            })
+        } else {
             Err(Error::runtime_error(
-                &expr.operator,
-                "Invalid binary operator",
+                name,
+                "Only instances have fields.",
             ))
+        }
+    }
+
+    pub fn set_property(&self, object: Object, name: &Token, value: Object) -> Result<(), Error> {
+        if let Object::Instance(inst) = object {
+            inst.set(name, value, &inst, self)
         } else {
-            Ok(result)
+            Err(Error::runtime_error(
+                name,
+                "Only instances have fields.",
+            ))
         }
     }
 
-    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<Object, Error> {
-        let callee = self.evaluate(expr.callee.clone())?;
+    /// Looks up `super.method` for `object`, the `this` of the calling method.
+    pub fn get_super(&self, superclass: &ClassStruct, object: &Object, method: &Token) -> Result<Object, Error> {
+        if let Some(Object::Function(getter)) = superclass.find_getter(&method.lexeme) {
+            if let Object::Function(getter) = getter.bind(object) {
+                // As in `InstanceStruct::call_accessor`, errors without a
+                // token of their own are reported at the property.
+                return getter.call(self, &[], None).map_err(|e| match e {
+                    Error::CallError { message } => Error::runtime_error(method, &message),
+                    e => e,
+                });
+            }
+        }
 
-        let mut arguments = Vec::new();
+        if let Some(method) = superclass.find_method(&method.lexeme) {
+            if let Object::Function(func) = method {
+                Ok(func.bind(object))
+            } else {
+                panic!("method was not a function");
+            }
+        } else {
+            Err(Error::runtime_error(
+                method,
+                &format!("Undefined property '{}'.", method.lexeme),
+            ))
+        }
+    }
 
-        for argument in expr.arguments.clone() {
-            arguments.push(self.evaluate(argument)?);
+    pub fn index(&self, bracket: &Token, object: Object, index: Object) -> Result<Object, Error> {
+        match object {
+            Object::List(list) => {
+                let i = self.list_index(bracket, &index, list.borrow().len())?;
+                let element = list.borrow()[i].clone();
+                Ok(element)
+            }
+            Object::Map(map) => {
+                let key = self.map_key(bracket, &index)?;
+                let value = map.borrow().get(key);
+                value.ok_or_else(|| {
+                    Error::runtime_error(bracket, &format!("Key {:?} not found in map.", key))
+                })
+            }
+            _ => Err(Error::runtime_error(bracket, "Only lists and maps can be indexed.")),
         }
+    }
 
-        let (callfunc, cls): (Option<Rc<dyn CallableTrait>>, Option<Rc<ClassStruct>>) = match callee
-        {
-            Object::Function(func) => (Some(func), None),
-            Object::Native(native) => (Some(native.func.clone()), None),
-            Object::Class(cls) => {
-                let class = Rc::clone(&cls);
-                (Some(cls), Some(class))
+    pub fn set_index(&self, bracket: &Token, object: Object, index: Object, value: Object) -> Result<Object, Error> {
+        match object {
+            Object::List(list) => {
+                let i = self.list_index(bracket, &index, list.borrow().len())?;
+                list.borrow_mut()[i] = value.clone();
+                Ok(value)
             }
-            _ => (None, None),
-        };
+            Object::Map(map) => {
+                let key = self.map_key(bracket, &index)?;
+                map.borrow_mut().insert(key.clone(), value.clone());
+                Ok(value)
+            }
+            _ => Err(Error::runtime_error(bracket, "Only lists and maps can be indexed.")),
+        }
+    }
 
-        if let Some(callfunc) = callfunc {
-            if arguments.len() != callfunc.arity() {
-                return Err(Error::runtime_error(
-                    &expr.paren,
-                    &format!(
-                        "Expected {} arguments but got {}.",
-                        callfunc.arity(),
-                        arguments.len()
-                    ),
+    pub fn new_class_id(&self) -> usize {
+        let id = self.next_class_id.get();
+        self.next_class_id.set(id + 1);
+        id
+    }
+
+    /// The random number generator shared by the random natives.
+    pub fn rng(&self) -> RefMut<'_, Rng> {
+        self.rng.borrow_mut()
+    }
+
+    /// The stack compiled code runs on.
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Counts a call into arc code. Fails, rather than letting the host
+    /// stack overflow, if that would go past the recursion limit.
+    pub fn enter_call(&self) -> Result<(), Error> {
+        if self.call_depth.get() >= self.recursion_limit {
+            return Err(Error::call_error("Maximum recursion depth exceeded."));
+        }
+        self.call_depth.set(self.call_depth.get() + 1);
+        Ok(())
+    }
+
+    /// Counts a call into arc code returning.
+    pub fn exit_call(&self) {
+        self.call_depth.set(self.call_depth.get() - 1);
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_depth.get()
+    }
+
+    pub fn set_call_depth(&self, depth: usize) {
+        self.call_depth.set(depth);
+    }
+
+    /// The id of the class whose code is currently running, if any.
+    pub fn current_class(&self) -> Option<usize> {
+        self.current_class.get()
+    }
+
+    pub fn set_current_class(&self, class: Option<usize>) -> Option<usize> {
+        self.current_class.replace(class)
+    }
+
+    fn accessors(&self, declarations: &Rc<Vec<Rc<Stmt>>>, class_id: usize) -> HashMap<RcStr, Object> {
+        let mut accessors = HashMap::new();
+        for declaration in declarations.iter() {
+            if let Stmt::Function(func) = declaration.deref() {
+                let function = Object::Function(Function::new(
+                    func,
+                    self.environment.borrow().deref(),
+                    false,
+                    Some(class_id),
                 ));
+                accessors.insert(func.name.lexeme.clone(), function);
             }
-            callfunc.call(self, &arguments, cls)
-        } else {
-            Err(Error::runtime_error(
-                &expr.paren,
-                "Can only call functions and classes.",
-            ))
         }
+        accessors
     }
 
-    fn visit_variable_expr(&self, wrapper: Rc<Expr>, expr: &VariableExpr) -> Result<Object, Error> {
-        self.look_up_variable(&expr.name, wrapper)
+    fn evaluate_names<T>(
+        &self,
+        exprs: &[Rc<Expr>],
+        message: &str,
+        extract: fn(Object) -> Option<Rc<T>>,
+    ) -> Result<Vec<Rc<T>>, Error> {
+        let mut values = Vec::new();
+        for expr in exprs {
+            match extract(self.evaluate(expr.clone())?) {
+                Some(value) => values.push(value),
+                None => {
+                    if let Expr::Variable(v) = expr.deref() {
+                        return Err(Error::runtime_error(&v.name, message));
+                    }
+                    panic!("could not extract variable expr");
+                }
+            }
+        }
+        Ok(values)
     }
-}
 
-impl Interpreter {
-    pub fn new() -> Interpreter {
-        let global = Rc::new(RefCell::new(Environment::new()));
+    fn signatures(declarations: &Rc<Vec<Rc<Stmt>>>) -> Vec<(String, usize)> {
+        let mut signatures = Vec::new();
+        for declaration in declarations.iter() {
+            if let Stmt::Function(func) = declaration.deref() {
+                signatures.push((func.name.lexeme.to_string(), func.params.len()));
+            }
+        }
+        signatures
+    }
 
-        global.borrow_mut().define(
-            "clock".to_string(),
-            Object::Native(Rc::new(Native {
-                func: Rc::new(NativeClock {}),
-            })),
-        );
+    /// Mixes in the traits, checks the class against its traits and
+    /// interfaces, and creates it.
+    pub fn build_class(
+        &self,
+        name: &Token,
+        id: usize,
+        superclass: Option<Rc<ClassStruct>>,
+        traits: Vec<Rc<TraitStruct>>,
+        interfaces: Vec<Rc<InterfaceStruct>>,
+        mut members: ClassMembers,
+    ) -> Result<Rc<ClassStruct>, Error> {
+        self.compose_traits(name, &mut members, &superclass, &traits)?;
+        for interface in interfaces.iter() {
+            self.check_required(
+                name,
+                &members,
+                &superclass,
+                &interface.methods,
+                &format!("interface '{}'", interface.name),
+            )?;
+        }
 
-        Interpreter {
-            globals: Rc::clone(&global),
-            environment: RefCell::new(Rc::clone(&global)),
-            locals: RefCell::new(HashMap::new()),
+        let class = Rc::new(ClassStruct::new(
+            id,
+            name.lexeme.to_string(),
+            superclass,
+            traits,
+            interfaces,
+            members,
+        ));
+        gc::track(&class);
+        Ok(class)
+    }
+
+    // Trait methods are copied into the class unless the class defines its
+    // own, so the class always wins and two traits may not disagree.
+    fn compose_traits(
+        &self,
+        name: &Token,
+        members: &mut ClassMembers,
+        superclass: &Option<Rc<ClassStruct>>,
+        traits: &[Rc<TraitStruct>],
+    ) -> Result<(), Error> {
+        let mut provided: HashMap<RcStr, (Object, String)> = HashMap::new();
+        for t in traits {
+            for (method, function) in t.methods.iter() {
+                if members.methods.contains_key(method) {
+                    continue;
+                }
+                if let Some((_, other)) = provided.get(method) {
+                    return Err(Error::runtime_error(
+                        name,
+                        &format!(
+                            "Method '{}' is provided by both trait '{}' and trait '{}'.",
+                            method, other, t.name
+                        ),
+                    ));
+                }
+                provided.insert(method.clone(), (function.clone(), t.name.clone()));
+            }
         }
+        for (method, (function, _)) in provided {
+            members.methods.insert(method, function);
+        }
+
+        for t in traits {
+            self.check_required(name, members, superclass, &t.required, &format!("trait '{}'", t.name))?;
+        }
+        Ok(())
+    }
+
+    // A required method is satisfied by a concrete or abstract method with
+    // the same arity, declared on the class or anywhere above it.
+    fn check_required(
+        &self,
+        name: &Token,
+        members: &ClassMembers,
+        superclass: &Option<Rc<ClassStruct>>,
+        required: &[(String, usize)],
+        source: &str,
+    ) -> Result<(), Error> {
+        for (method, arity) in required {
+            let found = if let Some(Object::Function(function)) = members.methods.get(method.as_str()) {
+                Some(function.arity())
+            } else if let Some((_, arity)) = members.abstract_methods.iter().find(|(n, _)| n == method) {
+                Some(*arity)
+            } else if let Some(superclass) = superclass {
+                match superclass.find_method(method) {
+                    Some(Object::Function(function)) => Some(function.arity()),
+                    _ => superclass.find_abstract(method),
+                }
+            } else {
+                None
+            };
+
+            match found {
+                Some(found) if found == *arity => {}
+                Some(_) => {
+                    return Err(Error::runtime_error(
+                        name,
+                        &format!(
+                            "Method '{}' must take {} arguments as required by {}.",
+                            method, arity, source
+                        ),
+                    ));
+                }
+                None => {
+                    return Err(Error::runtime_error(
+                        name,
+                        &format!(
+                            "Class '{}' must implement method '{}' required by {}.",
+                            name.lexeme, method, source
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
     }
 
     fn execute(&self, stmt: Rc<Stmt>) -> Result<(), Error> {
+        self.meter.step()?;
         stmt.accept(stmt.clone(), self)
     }
 
@@ -422,7 +868,7 @@ impl Interpreter {
         statements: &Rc<Vec<Rc<Stmt>>>,
         environment: Environment,
     ) -> Result<(), Error> {
-        let previous = self.environment.replace(Rc::new(RefCell::new(environment)));
+        let previous = self.environment.replace(Some(Rc::new(RefCell::new(environment))));
 
         let result = statements
             .iter()
@@ -433,45 +879,212 @@ impl Interpreter {
         result
     }
 
-    pub fn interpret(&self, statements: &[Rc<Stmt>]) -> bool {
-        let mut success = true;
-        for statement in statements {
-            if self.execute(statement.clone()).is_err() {
-                success = false;
-                break;
-            }
+    /// Runs a resolved program, stopping at the first error. A
+    /// `LimitError` means a sandboxed program ran into one of its limits.
+    pub fn interpret(&self, statements: &[Rc<Stmt>]) -> Result<(), Error> {
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement.clone()));
+        let _ = self.flush_output();
+        // A call error with no call site left to report it at.
+        if let Err(error @ Error::CallError { .. }) = &result {
+            error.report("");
         }
-        success
+        result
+    }
+
+    /// Sends program output to `output` instead of stdout, returning the
+    /// previous writer.
+    pub fn set_output(&self, output: Box<dyn Write>) -> Box<dyn Write> {
+        self.output.replace(output)
+    }
+
+    /// Writes program output, counting it against the output limit.
+    pub fn write_output(&self, text: &str) -> Result<(), Error> {
+        self.meter.output(text.len())?;
+        self.output
+            .borrow_mut()
+            .write_all(text.as_bytes())
+            .map_err(|e| Error::system_error(&format!("Failed to write output: {e}.")))
+    }
+
+    /// Counts a step of a running script against its limits. The
+    /// tree-walker counts statements, the VM instructions.
+    #[inline]
+    pub fn step(&self) -> Result<(), Error> {
+        self.meter.step()
+    }
+
+    pub fn flush_output(&self) -> io::Result<()> {
+        self.output.borrow_mut().flush()
     }
 
     fn evaluate(&self, expr: Rc<Expr>) -> Result<Object, Error> {
         expr.accept(expr.clone(), self)
     }
 
-    fn is_truthy(&self, object: Object) -> bool {
+    /// Calls a function, native or class. Natives use this to call back
+    /// into arc code. Errors without a token of their own come back as call
+    /// errors, to be reported at the call site.
+    pub fn call(&self, callee: &Object, mut arguments: Vec<Object>, keywords: Vec<(&Token, Object)>) -> Result<Object, Error> {
+        let (callfunc, cls): (Rc<dyn CallableTrait>, Option<Rc<ClassStruct>>) = match callee {
+            Object::Function(func) => (func.clone(), None),
+            Object::Native(native) => (native.func.clone(), None),
+            Object::Class(cls) => (cls.clone(), Some(Rc::clone(cls))),
+            _ => return Err(Error::call_error("Can only call functions and classes.")),
+        };
+
+        let keyword_arguments = self.keyword_arguments(callfunc.keywords(), keywords)?;
+        if callfunc.is_variadic() && arguments.len() < callfunc.arity() {
+            return Err(Error::call_error(&format!(
+                "Expected at least {} arguments but got {}.",
+                callfunc.arity(),
+                arguments.len()
+            )));
+        }
+        if !callfunc.is_variadic() && arguments.len() != callfunc.arity() {
+            return Err(Error::call_error(&format!(
+                "Expected {} arguments but got {}.",
+                callfunc.arity(),
+                arguments.len()
+            )));
+        }
+        arguments.extend(keyword_arguments);
+        callfunc.call(self, &arguments, cls)
+    }
+
+    // Orders keyword argument values to match the slots the callee declares.
+    fn keyword_arguments(&self, accepted: &[&str], keywords: Vec<(&Token, Object)>) -> Result<Vec<Object>, Error> {
+        let mut slots = vec![Object::Nil; accepted.len()];
+        for (name, value) in keywords {
+            match accepted.iter().position(|k| name.lexeme == *k) {
+                Some(slot) => slots[slot] = value,
+                None => {
+                    return Err(Error::runtime_error(
+                        name,
+                        &format!("Unexpected keyword argument '{}'.", name.lexeme),
+                    ))
+                }
+            }
+        }
+        Ok(slots)
+    }
+
+    pub fn map_key<'a>(&self, token: &Token, key: &'a Object) -> Result<&'a RcStr, Error> {
+        match key {
+            Object::Str(key) => Ok(key),
+            _ => Err(Error::runtime_error(token, "Map keys must be strings.")),
+        }
+    }
+
+    fn list_index(&self, bracket: &Token, index: &Object, len: usize) -> Result<usize, Error> {
+        match index {
+            Object::Num(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => Ok(*n as usize),
+            Object::Num(n) if n.fract() == 0.0 => Err(Error::runtime_error(
+                bracket,
+                &format!("List index {} out of range for length {}.", n, len),
+            )),
+            _ => Err(Error::runtime_error(bracket, "List index must be an integer.")),
+        }
+    }
+
+    pub fn is_truthy(&self, object: Object) -> bool {
+        Self::truthy(&object)
+    }
+
+    /// Only nil and false are falsy.
+    pub fn truthy(object: &Object) -> bool {
         match object {
             Object::Nil => false,
-            Object::Bool(x) => x,
+            Object::Bool(x) => *x,
             _ => true,
         }
     }
 
     pub fn print_env(&self) {
-        println!("{:?}", self.environment.borrow());
+        match self.environment.borrow().deref() {
+            Some(environment) => println!("{:?}", environment.borrow()),
+            None => println!("{:?}", self.globals.borrow()),
+        }
     }
 
-    pub fn resolve(&self, expr: Rc<Expr>, depth: usize) {
-        self.locals.borrow_mut().insert(expr, depth);
+    // The innermost local scope. Only called with a slot from the resolver,
+    // which means we are inside one.
+    fn current_environment(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(self.environment.borrow().as_ref().unwrap())
     }
 
-    fn look_up_variable(&self, name: &Token, expr: Rc<Expr>) -> Result<Object, Error> {
-        if let Some(distance) = self.locals.borrow().get(&expr) {
-            self.environment
-                .borrow()
-                .borrow()
-                .get_at(*distance, &name.lexeme)
-        } else {
-            self.globals.borrow().get(&name)
+    // Declarations at the top level become globals. Anywhere else they take
+    // the next slot of the current scope, matching the resolver's numbering.
+    fn define(&self, name: &Token, value: Object) {
+        match self.environment.borrow().deref() {
+            Some(environment) => environment.borrow_mut().define(value),
+            None => self.globals.borrow_mut().define(name.lexeme.clone(), value),
+        }
+    }
+
+    fn look_up_variable(&self, name: &Token, slot: Option<Slot>) -> Result<Object, Error> {
+        match slot {
+            Some(slot) => Ok(self.current_environment().borrow().get_at(slot.depth, slot.index)),
+            None => self.globals.borrow().get(name),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::{prepare, run, Backend};
+
+    /// A writer whose contents stay readable after the interpreter takes it.
+    #[derive(Clone, Default)]
+    pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
+
+    impl Buffer {
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn output_goes_to_the_writer_set() {
+        let source = r#"
+            print (1) + 2;
+            print("a", "b", sep: "-", end: "!");
+            print();
+            print("done", end: "");
+        "#;
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let interpreter = Interpreter::new();
+            let buffer = Buffer::default();
+            interpreter.set_output(Box::new(buffer.clone()));
+            let program = prepare(source).unwrap();
+            assert!(run(&interpreter, backend, &program).is_ok());
+            assert_eq!(buffer.text(), "3\na-b!\ndone");
+        }
+    }
+
+    #[test]
+    fn set_output_returns_the_previous_writer() {
+        let interpreter = Interpreter::new();
+        let first = Buffer::default();
+        interpreter.set_output(Box::new(first.clone()));
+        let second = Buffer::default();
+        let mut previous = interpreter.set_output(Box::new(second.clone()));
+        previous.write_all(b"x").unwrap();
+        interpreter.write_output("y").unwrap();
+        interpreter.flush_output().unwrap();
+        assert_eq!(first.text(), "x");
+        assert_eq!(second.text(), "y");
+    }
 }
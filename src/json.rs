@@ -0,0 +1,409 @@
+use crate::errors::*;
+use crate::gc;
+use crate::interpreter::*;
+use crate::map::*;
+use crate::module::*;
+use crate::native_functions::*;
+use crate::reflection::type_name;
+use crate::tokens::*;
+use std::collections::{HashMap, HashSet};
+
+// Deeper documents are rejected, when parsing or writing them, rather than
+// risking the native stack.
+const MAX_DEPTH: usize = 512;
+
+pub fn module() -> ModuleStruct {
+    let mut members = HashMap::new();
+    for native in [
+        NativeFunction::new("parse", 1, native_parse),
+        NativeFunction::variadic("stringify", 1, native_stringify),
+    ] {
+        members.insert(native.name().to_string(), native.into_object());
+    }
+    ModuleStruct::new("json", members)
+}
+
+fn native_parse(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let text = string_arg(&args[0], "json.parse")?;
+    let mut parser = JsonParser {
+        chars: text.chars().collect(),
+        current: 0,
+    };
+    parser.skip_whitespace();
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.current < parser.chars.len() {
+        return Err(parser.error("unexpected data after the value"));
+    }
+    Ok(value)
+}
+
+fn native_stringify(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let indent = match args {
+        [_] | [_, Object::Nil] => 0,
+        [_, indent] => count_arg(indent, "json.stringify")?,
+        _ => {
+            return Err(Error::call_error(&format!(
+                "Expected at most 2 arguments but got {}.",
+                args.len()
+            )))
+        }
+    };
+    let mut writer = JsonWriter {
+        interpreter: terp,
+        indent,
+        out: String::new(),
+        path: HashSet::new(),
+    };
+    writer.value(&args[0], 0)?;
+    Ok(Object::Str(writer.out.into()))
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl JsonParser {
+    fn error(&self, message: &str) -> Error {
+        Error::call_error(&format!(
+            "json.parse() found invalid JSON at position {}: {}.",
+            self.current, message
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.current += 1;
+        c
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        if self.peek() == Some(expected) {
+            self.current += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{expected}'")))
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.current += 1;
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Object) -> Result<Object, Error> {
+        for expected in word.chars() {
+            if self.advance() != Some(expected) {
+                self.current -= 1;
+                return Err(self.error("unexpected character"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Object, Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("nesting is too deep"));
+        }
+        match self.peek() {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => Ok(Object::Str(self.string()?.into())),
+            Some('t') => self.literal("true", Object::Bool(true)),
+            Some('f') => self.literal("false", Object::Bool(false)),
+            Some('n') => self.literal("null", Object::Nil),
+            Some('-' | '0'..='9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Object, Error> {
+        self.expect('{')?;
+        let mut map = MapStruct::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.current += 1;
+            return Ok(Object::map(map));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            let value = self.value(depth + 1)?;
+            map.insert(key.into(), value);
+            self.skip_whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some('}') => return Ok(Object::map(map)),
+                _ => {
+                    self.current -= 1;
+                    return Err(self.error("expected ',' or '}'"));
+                }
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Object, Error> {
+        self.expect('[')?;
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.current += 1;
+            return Ok(Object::list(elements));
+        }
+        loop {
+            self.skip_whitespace();
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some(']') => return Ok(Object::list(elements)),
+                _ => {
+                    self.current -= 1;
+                    return Err(self.error("expected ',' or ']'"));
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.advance() {
+                Some('"') => return Ok(value),
+                Some('\\') => value.push(self.escape()?),
+                Some(c) if (c as u32) < 0x20 => {
+                    self.current -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, Error> {
+        match self.advance() {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let high = self.hex4()?;
+                if !(0xD800..0xDC00).contains(&high) {
+                    return char::from_u32(high).ok_or_else(|| self.error("invalid \\u escape"));
+                }
+                // A high surrogate must be followed by an escaped low one.
+                if self.advance() != Some('\\') || self.advance() != Some('u') {
+                    return Err(self.error("unpaired surrogate in \\u escape"));
+                }
+                let low = self.hex4()?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(self.error("unpaired surrogate in \\u escape"));
+                }
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                char::from_u32(code).ok_or_else(|| self.error("invalid \\u escape"))
+            }
+            _ => Err(self.error("invalid escape")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .advance()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid \\u escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Object, Error> {
+        let start = self.current;
+        if self.peek() == Some('-') {
+            self.current += 1;
+        }
+        match self.peek() {
+            Some('0') => self.current += 1,
+            Some('1'..='9') => self.digits(),
+            _ => return Err(self.error("invalid number")),
+        }
+        if self.peek() == Some('.') {
+            self.current += 1;
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.digits();
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            self.current += 1;
+            if matches!(self.peek(), Some('+' | '-')) {
+                self.current += 1;
+            }
+            if !matches!(self.peek(), Some('0'..='9')) {
+                return Err(self.error("invalid number"));
+            }
+            self.digits();
+        }
+        let text: String = self.chars[start..self.current].iter().collect();
+        text.parse::<f64>()
+            .map(Object::Num)
+            .map_err(|_| self.error("invalid number"))
+    }
+
+    fn digits(&mut self) {
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.current += 1;
+        }
+    }
+}
+
+struct JsonWriter<'a> {
+    interpreter: &'a Interpreter,
+    indent: usize,
+    out: String,
+    /// Addresses of the containers being written, to detect cycles.
+    path: HashSet<usize>,
+}
+
+impl JsonWriter<'_> {
+    fn value(&mut self, value: &Object, depth: usize) -> Result<(), Error> {
+        match value {
+            Object::Nil => self.out.push_str("null"),
+            Object::Bool(b) => self.out.push_str(&b.to_string()),
+            Object::Num(n) if n.is_finite() => self.out.push_str(&n.to_string()),
+            Object::Num(n) => {
+                return Err(Error::call_error(&format!(
+                    "json.stringify() cannot represent the number {n}."
+                )))
+            }
+            Object::Str(s) => self.string(s),
+            Object::List(list) => {
+                let address = gc::address(list);
+                self.enter(address, depth)?;
+                let elements = list.borrow().clone();
+                self.sequence('[', ']', elements.len(), depth, |writer, i| {
+                    writer.value(&elements[i], depth + 1)
+                })?;
+                self.path.remove(&address);
+            }
+            Object::Map(map) => {
+                let address = gc::address(map);
+                self.enter(address, depth)?;
+                let entries = map.borrow().entries().to_vec();
+                self.sequence('{', '}', entries.len(), depth, |writer, i| {
+                    writer.entry(&entries[i].0, &entries[i].1, depth)
+                })?;
+                self.path.remove(&address);
+            }
+            Object::Instance(instance) => {
+                let address = gc::address(instance);
+                self.enter(address, depth)?;
+                let mut entries = Vec::new();
+                for name in instance.field_names() {
+                    let token = Token::new(TokenKind::Identifier, name.as_str().into(), None, 0);
+                    entries.push((name, instance.get(&token, instance, self.interpreter)?));
+                }
+                self.sequence('{', '}', entries.len(), depth, |writer, i| {
+                    writer.entry(&entries[i].0, &entries[i].1, depth)
+                })?;
+                self.path.remove(&address);
+            }
+            _ => {
+                return Err(Error::call_error(&format!(
+                    "json.stringify() cannot serialize a value of type {}.",
+                    type_name(value)
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, address: usize, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::call_error("json.stringify() found nesting that is too deep."));
+        }
+        if !self.path.insert(address) {
+            return Err(Error::call_error(
+                "json.stringify() cannot serialize a value that contains itself.",
+            ));
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, key: &str, value: &Object, depth: usize) -> Result<(), Error> {
+        self.string(key);
+        self.out.push(':');
+        if self.indent > 0 {
+            self.out.push(' ');
+        }
+        self.value(value, depth + 1)
+    }
+
+    // Writes `len` items between `open` and `close`, one per line when
+    // indenting.
+    fn sequence(
+        &mut self,
+        open: char,
+        close: char,
+        len: usize,
+        depth: usize,
+        mut item: impl FnMut(&mut Self, usize) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        self.out.push(open);
+        for i in 0..len {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.newline(depth + 1);
+            item(self, i)?;
+        }
+        if len > 0 {
+            self.newline(depth);
+        }
+        self.out.push(close);
+        Ok(())
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if self.indent > 0 {
+            self.out.push('\n');
+            self.out.push_str(&" ".repeat(self.indent * depth));
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if (c as u32) < 0x20 => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
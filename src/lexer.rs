@@ -1,7 +1,9 @@
 use crate::tokens::*;
+use crate::interner::intern;
 use crate::errors::*;
 pub struct Lexer {
-    input: String,
+    // Held as chars so positions stay valid for non-ASCII source.
+    input: Vec<char>,
     start: usize,
     current: usize,
     line: usize,
@@ -11,7 +13,7 @@ pub struct Lexer {
 impl Lexer {
     pub fn new(input: String) -> Lexer {
         Lexer {
-            input: input + "\0",
+            input: (input + "\0").chars().collect(),
             start: 0,
             current: 0,
             line: 1,
@@ -20,11 +22,11 @@ impl Lexer {
     }
 
     pub fn current_char(&self) -> char {
-        self.input.chars().nth(self.current).unwrap_or('\0')
+        self.input.get(self.current).copied().unwrap_or('\0')
     }
 
     pub fn next_char(&self) -> char {
-        self.input.chars().nth(self.current + 1).unwrap_or('\0')
+        self.input.get(self.current + 1).copied().unwrap_or('\0')
     }
 
     pub fn advance(&mut self) -> char {
@@ -49,34 +51,40 @@ impl Lexer {
     }
 
     fn number(&mut self) -> Option<Token> {
-        while self.current_char().is_digit(10) {
+        while self.current_char().is_ascii_digit() {
             self.advance();
         }
 
-        if self.current_char() == '.' && self.next_char().is_digit(10) {
+        if self.current_char() == '.' && self.next_char().is_ascii_digit() {
             self.advance();
 
-            while self.current_char().is_digit(10) {
+            while self.current_char().is_ascii_digit() {
                 self.advance();
             }
         }
 
-        let literal = self.input[self.start..self.current].to_string();
+        let literal = self.text(self.start, self.current);
 
         Some(self.add_token_with_literal(TokenKind::Number, Object::Num(literal.parse::<f64>().unwrap())))
     }
 
     fn string(&mut self) -> Option<Token> {
+        let mut value = String::new();
         while self.current_char() != '"' && !self.is_at_end() {
             if self.current_char() == '\n' {
                 self.line += 1;
             }
-            self.advance();
+            let c = self.advance();
+            if c == '\\' {
+                value.push_str(&self.escape());
+            } else {
+                value.push(c);
+            }
         }
 
         if self.is_at_end() {
             Error::lexer_error(
-                &self.input[self.start..self.current].to_string(),
+                &self.text(self.start, self.current),
                 &self.line.to_string(),
                 "unterminated string.",
             );
@@ -86,27 +94,42 @@ impl Lexer {
 
         self.advance();
 
-        let literal = self.input[self.start + 1..self.current - 1].to_string();
+        Some(self.add_token_with_literal(TokenKind::String, Object::Str(intern(&value))))
+    }
 
-        Some(self.add_token_with_literal(TokenKind::String, Object::Str(literal)))
+    // Unknown escapes are kept as written, so a lone backslash still means
+    // a backslash.
+    fn escape(&mut self) -> String {
+        let escaped = match self.current_char() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            _ => return "\\".to_string(),
+        };
+        self.advance();
+        escaped.to_string()
     }
 
-    fn comment(&mut self) -> Option<Token> {
-        while self.current_char() != '\n' && !self.is_at_end() {
-            self.skip_whitespace();
+    // Comments run to the end of the line and make no token.
+    fn comment(&mut self) {
+        while self.current_char() != '\n' && self.current_char() != '\0' {
             self.advance();
         }
+    }
 
-        Some(self.add_token(TokenKind::Comment))
-
+    fn text(&self, start: usize, end: usize) -> String {
+        self.input[start..end].iter().collect()
     }
 
     fn add_token(&self, kind: TokenKind) -> Token {
-        Token::new(kind, self.input[self.start..self.current].to_string(), None, self.line)
+        Token::new(kind, intern(&self.text(self.start, self.current)), None, self.line)
     }
 
     fn add_token_with_literal(&self, kind: TokenKind, literal: Object) -> Token {
-        Token::new(kind, self.input[self.start..self.current].to_string(), Some(literal), self.line)
+        Token::new(kind, intern(&self.text(self.start, self.current)), Some(literal), self.line)
     }
 
     fn is_at_end(&self) -> bool {
@@ -118,7 +141,7 @@ impl Lexer {
     }
 }
 
-impl<'a> Iterator for &'a mut Lexer {
+impl Iterator for &mut Lexer {
     type Item = Token;
     fn next(&mut self) -> Option<Self::Item> {
         if self.is_at_end() {
@@ -134,9 +157,10 @@ impl<'a> Iterator for &'a mut Lexer {
             ')' => Some(self.add_token(TokenKind::RightParen)),
             '{' => Some(self.add_token(TokenKind::LeftBrace)),
             '}' => Some(self.add_token(TokenKind::RightBrace)),
-            '[' => Some(self.add_token(TokenKind::RightSquare)),
-            ']' => Some(self.add_token(TokenKind::LeftSquare)),
+            '[' => Some(self.add_token(TokenKind::LeftSquare)),
+            ']' => Some(self.add_token(TokenKind::RightSquare)),
             ',' => Some(self.add_token(TokenKind::Comma)),
+            ':' => Some(self.add_token(TokenKind::Colon)),
             '.' => Some(self.add_token(TokenKind::Dot)),
             '-' => Some(self.add_token(TokenKind::Minus)),
             '+' => Some(self.add_token(TokenKind::Plus)),
@@ -181,7 +205,19 @@ impl<'a> Iterator for &'a mut Lexer {
 
             '/' => Some(self.add_token(TokenKind::Slash)),
 
-            '~' => self.comment(),
+            '~' => {
+                self.comment();
+                self.next()
+            }
+
+            '#' if self.current_char().is_alphabetic() || self.current_char() == '_' => {
+                while self.current_char().is_alphanumeric() {
+                    self.advance();
+                }
+
+                let literal = self.text(self.start, self.current);
+                Some(self.add_token_with_literal(TokenKind::PrivateIdentifier, Object::Str(intern(&literal))))
+            }
 
             '"' => self.string(),
 
@@ -196,9 +232,9 @@ impl<'a> Iterator for &'a mut Lexer {
                     self.advance();
                 }
 
-                let text = &self.input[self.start..self.current];
+                let text = self.text(self.start, self.current);
 
-                match text {
+                match text.as_str() {
                     "and" => Some(self.add_token(TokenKind::And)),
                     "class" => Some(self.add_token(TokenKind::Class)),
                     "else" => Some(self.add_token(TokenKind::Else)),
@@ -206,25 +242,29 @@ impl<'a> Iterator for &'a mut Lexer {
                     "for" => Some(self.add_token(TokenKind::For)),
                     "fn" => Some(self.add_token(TokenKind::Fn)),
                     "if" => Some(self.add_token(TokenKind::If)),
+                    "implements" => Some(self.add_token(TokenKind::Implements)),
+                    "interface" => Some(self.add_token(TokenKind::Interface)),
                     "nil" => Some(self.add_token(TokenKind::Nil)),
                     "or" => Some(self.add_token(TokenKind::Or)),
                     "print" => Some(self.add_token(TokenKind::Print)),
                     "return" => Some(self.add_token(TokenKind::Return)),
                     "super" => Some(self.add_token(TokenKind::Super)),
                     "this" => Some(self.add_token(TokenKind::This)),
+                    "trait" => Some(self.add_token(TokenKind::Trait)),
                     "true" => Some(self.add_token(TokenKind::True)),
                     "var" => Some(self.add_token(TokenKind::Var)),
                     "while" => Some(self.add_token(TokenKind::While)),
+                    "with" => Some(self.add_token(TokenKind::With)),
                     _ => {
-                        let literal = self.input[self.start..self.current].to_string();
-                        Some(self.add_token_with_literal(TokenKind::Identifier, Object::Str(literal)))
+                        let literal = self.text(self.start, self.current);
+                        Some(self.add_token_with_literal(TokenKind::Identifier, Object::Str(intern(&literal))))
                     },
                 }
             }
 
             _ => {
                 Error::lexer_error(
-                    &self.input[self.start..self.current].to_string(),
+                    &self.text(self.start, self.current),
                     &self.line.to_string(),
                     "unexpected character.",
                 );
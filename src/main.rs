@@ -1,47 +1,122 @@
 mod callable;
+mod collections;
+mod compiler;
+mod datetime;
 mod enviroment;
 mod errors;
 mod expr;
+mod files;
 mod functions;
+mod gc;
+mod input;
 mod instance;
+mod interner;
 mod interpreter;
+mod json;
 mod lexer;
+mod map;
+mod math;
+mod module;
 mod native_functions;
+mod optimizer;
 mod parser;
+mod random;
+mod process;
+mod reflection;
+mod regex;
 mod resolver;
+mod sandbox;
 mod stmt;
+mod strings;
 mod tokens;
+mod traits;
+mod vm;
 
+use crate::compiler::Compiler;
 use crate::errors::*;
+use crate::interner::intern;
 use crate::lexer::Lexer;
+use crate::optimizer::Optimizer;
+use crate::sandbox::{Capability, Config};
 use interpreter::Interpreter;
 use parser::*;
 use resolver::*;
 use std::io::Write;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+use stmt::Stmt;
 use tokens::*;
 
-fn eval(source: &str) -> Result<(), Error> {
+/// Which backend runs programs.
+#[derive(Clone, Copy, PartialEq)]
+enum Backend {
+    TreeWalker,
+    Vm,
+}
+
+fn define_args(interpreter: &Interpreter, args: &[String]) {
+    let args = args.iter().map(|arg| Object::Str(arg.as_str().into())).collect();
+    interpreter.globals.borrow_mut().define(
+        intern("args"),
+        Object::list(args),
+    );
+}
+
+// The exit status of a script stopped by one of its sandbox limits.
+const LIMIT_EXIT: i32 = 3;
+
+// Runs a resolved program. Errors are reported as they happen, and the one
+// it stopped with is passed back, or None if it could not be compiled for
+// the VM.
+fn run(interpreter: &Interpreter, backend: Backend, statements: &Rc<Vec<Rc<Stmt>>>) -> Result<(), Option<Error>> {
+    match backend {
+        Backend::TreeWalker => interpreter.interpret(statements).map_err(Some),
+        Backend::Vm => {
+            let compiler = Compiler::new();
+            let script = compiler.compile_script(statements);
+            if !compiler.success() {
+                return Err(None);
+            }
+            vm::interpret(interpreter, script).map_err(Some)
+        }
+    }
+}
+
+// Lexes, parses and resolves a program. Errors are reported as they are
+// found, and None means there were some.
+fn resolve(source: &str) -> Option<Rc<Vec<Rc<Stmt>>>> {
     let mut lexer = Lexer::new(source.to_string());
     let tokens: Vec<Token> = lexer.collect();
+    if !lexer.success() {
+        return None;
+    }
 
-    if lexer.success() {
-        let mut parser = Parser::new(tokens);
-        let statements = parser.parse();
+    let mut parser = Parser::new(tokens);
+    let s = Rc::new(parser.parse().ok()?);
+    let resolver = Resolver::new();
+    resolver.resolve(&Rc::clone(&s));
+    resolver.success().then_some(s)
+}
+
+// A program resolved and optimized, ready to run.
+fn prepare(source: &str) -> Option<Rc<Vec<Rc<Stmt>>>> {
+    resolve(source).map(|s| Optimizer::new().optimize(&s))
+}
 
-        let interpreter = Interpreter::new();
-        let s = Rc::new(statements?);
-        let resolver = Resolver::new(&interpreter);
-        resolver.resolve(&Rc::clone(&s));
-        if resolver.success() {
-            interpreter.interpret(&Rc::clone(&s));
+fn eval(source: &str, args: &[String], backend: Backend, config: &Config) {
+    if let Some(s) = prepare(source) {
+        let interpreter = Interpreter::with_config(config);
+        define_args(&interpreter, args);
+        if let Err(Some(Error::LimitError { .. })) = run(&interpreter, backend, &s) {
+            std::process::exit(LIMIT_EXIT);
         }
     }
-    Ok(())
 }
 
-fn repl() {
-    let interpreter = Interpreter::new();
+fn repl(backend: Backend, config: &Config) {
+    let interpreter = Interpreter::with_config(config);
+    define_args(&interpreter, &[]);
     println!(
         r#" 
         █████╗ ██████╗  ██████╗
@@ -74,11 +149,12 @@ fn repl() {
                     match statements {
                         Ok(statements) => {
                             let s = Rc::new(statements);
-                            let resolver = Resolver::new(&interpreter);
+                            let resolver = Resolver::new();
                             resolver.resolve(&Rc::clone(&s));
 
                             if resolver.success() {
-                                if !interpreter.interpret(&Rc::clone(&s)) {
+                                let s = Optimizer::new().optimize(&s);
+                                if run(&interpreter, backend, &s).is_err() {
                                     std::process::exit(1);
                                 }
                             }
@@ -91,18 +167,121 @@ fn repl() {
     }
 }
 
+// Enough host stack for the tree-walking interpreter to reach the default
+// recursion limit, even in debug builds. Only what is used gets committed.
+const STACK_SIZE: usize = 1 << 30;
+
+// The most `--recursion-limit` allows, since the host stack grows with it.
+const MAX_RECURSION_LIMIT: usize = 50_000;
+
+// Options come before the script name, and arguments after it are passed
+// to the script as `args`. `--vm` runs it on the bytecode VM instead of the
+// tree-walking interpreter. The rest sandbox it:
+//
+//   --max-steps <n>        stop after n statements, or VM instructions
+//   --timeout <seconds>    stop after this long
+//   --max-heap <bytes>     stop once this much more memory is in use
+//   --max-output <bytes>   stop before writing more output than this
+//   --deny <list>          leave out natives needing any of these comma
+//                          separated capabilities: files, processes, network
+//   --recursion-limit <n>  allow at most n nested calls, 10000 by default
 fn main() {
-    match std::env::args().len() {
-        1 => repl(),
-        2 => {
-            let args: Vec<String> = std::env::args().collect();
-            let filename = &args[1];
-            let source = std::fs::read_to_string(filename).unwrap();
-            let _ = eval(&source);
+    let (args, backend, config) = options();
+    // The host stack grows with the recursion limit, but not below the size
+    // the default limit needs.
+    let stack_size = (STACK_SIZE / interpreter::RECURSION_LIMIT)
+        .saturating_mul(config.recursion_limit)
+        .max(STACK_SIZE);
+    let main = std::thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || start(&args, backend, &config));
+    match main {
+        Ok(main) => {
+            if main.join().is_err() {
+                std::process::exit(70);
+            }
+        }
+        Err(e) => {
+            Error::system_error(&format!("Could not start the interpreter: {e}."));
+            std::process::exit(70);
+        }
+    }
+}
+
+// Takes the options off the command line, leaving the program name, the
+// script and its arguments.
+fn options() -> (Vec<String>, Backend, Config) {
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut backend = Backend::TreeWalker;
+    let mut config = Config::default();
+    while args.get(1).is_some_and(|arg| arg.starts_with("--")) {
+        let option = args.remove(1);
+        if option == "--vm" {
+            backend = Backend::Vm;
+            continue;
+        }
+        if args.len() < 2 {
+            usage(&format!("Option '{option}' needs a value."));
         }
+        let value = args.remove(1);
+        match option.as_str() {
+            "--max-steps" => config.max_steps = Some(option_value(&option, &value)),
+            "--timeout" => {
+                let seconds = option_value(&option, &value);
+                match Duration::try_from_secs_f64(seconds) {
+                    Ok(timeout) => config.timeout = Some(timeout),
+                    Err(_) => usage(&format!("Invalid value '{value}' for '{option}'.")),
+                }
+            }
+            "--max-heap" => config.max_heap = Some(option_value(&option, &value)),
+            "--max-output" => config.max_output = Some(option_value(&option, &value)),
+            "--deny" => {
+                for name in value.split(',') {
+                    match Capability::from_name(name) {
+                        Some(capability) => config.capabilities.remove(&capability),
+                        None => usage(&format!("Unknown capability '{name}'.")),
+                    };
+                }
+            }
+            "--recursion-limit" => {
+                let limit = option_value(&option, &value);
+                if !(1..=MAX_RECURSION_LIMIT).contains(&limit) {
+                    usage(&format!(
+                        "'{option}' must be between 1 and {MAX_RECURSION_LIMIT}."
+                    ));
+                }
+                config.recursion_limit = limit;
+            }
+            _ => usage(&format!("Unknown option '{option}'.")),
+        }
+    }
+    (args, backend, config)
+}
+
+fn start(args: &[String], backend: Backend, config: &Config) {
+    match args.len() {
+        1 => repl(backend, config),
         _ => {
-            println!("Usage: Arc [Filename]");
-            std::process::exit(64);
+            let filename = &args[1];
+            match std::fs::read_to_string(filename) {
+                Ok(source) => eval(&source, &args[2..], backend, config),
+                Err(e) => {
+                    Error::system_error(&format!("Could not read '{filename}': {e}."));
+                    std::process::exit(66);
+                }
+            }
         }
     }
 }
+
+fn option_value<T: FromStr>(option: &str, value: &str) -> T {
+    match value.parse() {
+        Ok(value) => value,
+        Err(_) => usage(&format!("Invalid value '{value}' for '{option}'.")),
+    }
+}
+
+fn usage(message: &str) -> ! {
+    Error::system_error(message);
+    std::process::exit(64);
+}
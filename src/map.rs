@@ -0,0 +1,74 @@
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
+use crate::tokens::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// A string-keyed map that remembers insertion order, so printing and
+/// serializing a map lists keys in the order they were added.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct MapStruct {
+    entries: Vec<(RcStr, Object)>,
+    index: HashMap<RcStr, usize>,
+}
+
+impl MapStruct {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Object> {
+        self.index.get(key).map(|&i| self.entries[i].1.clone())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Replaces the value of an existing key in place, keeping its position.
+    pub fn insert(&mut self, key: RcStr, value: Object) {
+        if let Some(&i) = self.index.get(&key) {
+            self.entries[i].1 = value;
+        } else {
+            self.index.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Object> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for position in self.index.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[(RcStr, Object)] {
+        &self.entries
+    }
+}
+
+impl Trace for RefCell<MapStruct> {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        let Ok(map) = self.try_borrow_mut() else {
+            return false;
+        };
+        map.entries.iter().for_each(|(_, value)| gc::object_refs(value, refs));
+        true
+    }
+
+    fn clear(&self) {
+        self.take();
+    }
+}
@@ -0,0 +1,90 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::module::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::f64::consts;
+
+pub fn module() -> ModuleStruct {
+    let mut members = HashMap::new();
+    for native in natives() {
+        members.insert(native.name().to_string(), native.into_object());
+    }
+    for (name, value) in [
+        ("pi", consts::PI),
+        ("e", consts::E),
+        ("inf", f64::INFINITY),
+        ("nan", f64::NAN),
+    ] {
+        members.insert(name.to_string(), Object::Num(value));
+    }
+    ModuleStruct::new("math", members)
+}
+
+fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("sqrt", 1, |_, args| unary(args, "sqrt", f64::sqrt)),
+        NativeFunction::new("pow", 2, native_pow),
+        NativeFunction::new("floor", 1, |_, args| unary(args, "floor", f64::floor)),
+        NativeFunction::new("ceil", 1, |_, args| unary(args, "ceil", f64::ceil)),
+        NativeFunction::new("round", 1, |_, args| unary(args, "round", f64::round)),
+        NativeFunction::new("abs", 1, |_, args| unary(args, "abs", f64::abs)),
+        NativeFunction::variadic("min", 1, |_, args| extreme(args, "min", f64::min)),
+        NativeFunction::variadic("max", 1, |_, args| extreme(args, "max", f64::max)),
+        NativeFunction::new("sin", 1, |_, args| unary(args, "sin", f64::sin)),
+        NativeFunction::new("cos", 1, |_, args| unary(args, "cos", f64::cos)),
+        NativeFunction::new("tan", 1, |_, args| unary(args, "tan", f64::tan)),
+        NativeFunction::new("asin", 1, |_, args| unary(args, "asin", f64::asin)),
+        NativeFunction::new("acos", 1, |_, args| unary(args, "acos", f64::acos)),
+        NativeFunction::new("atan", 1, |_, args| unary(args, "atan", f64::atan)),
+        NativeFunction::new("atan2", 2, native_atan2),
+        NativeFunction::new("exp", 1, |_, args| unary(args, "exp", f64::exp)),
+        NativeFunction::new("log", 1, |_, args| unary(args, "log", f64::ln)),
+        NativeFunction::new("log2", 1, |_, args| unary(args, "log2", f64::log2)),
+        NativeFunction::new("log10", 1, |_, args| unary(args, "log10", f64::log10)),
+        NativeFunction::new("isNan", 1, native_is_nan),
+        NativeFunction::new("isFinite", 1, native_is_finite),
+    ]
+}
+
+fn unary(args: &[Object], native: &str, f: fn(f64) -> f64) -> Result<Object, Error> {
+    Ok(Object::Num(f(number_arg(&args[0], native)?)))
+}
+
+// min and max take either their operands directly or a single list of them.
+fn extreme(args: &[Object], native: &str, pick: fn(f64, f64) -> f64) -> Result<Object, Error> {
+    let values = match args {
+        [Object::List(list)] => list.borrow().clone(),
+        _ => args.to_vec(),
+    };
+    if values.is_empty() {
+        return Err(Error::call_error(&format!("{native}() of an empty list.")));
+    }
+
+    let mut result = number_arg(&values[0], native)?;
+    for value in values[1..].iter() {
+        result = pick(result, number_arg(value, native)?);
+    }
+    Ok(Object::Num(result))
+}
+
+fn native_pow(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let base = number_arg(&args[0], "pow")?;
+    let exponent = number_arg(&args[1], "pow")?;
+    Ok(Object::Num(base.powf(exponent)))
+}
+
+fn native_atan2(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let y = number_arg(&args[0], "atan2")?;
+    let x = number_arg(&args[1], "atan2")?;
+    Ok(Object::Num(y.atan2(x)))
+}
+
+fn native_is_nan(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Bool(number_arg(&args[0], "isNan")?.is_nan()))
+}
+
+fn native_is_finite(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Bool(number_arg(&args[0], "isFinite")?.is_finite()))
+}
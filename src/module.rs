@@ -0,0 +1,29 @@
+use crate::tokens::*;
+use std::collections::HashMap;
+
+/// A named bundle of natives and constants, such as `math`. Members are
+/// read with `.` like instance fields, but a module cannot be modified.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleStruct {
+    pub name: String,
+    members: HashMap<String, Object>,
+}
+
+impl ModuleStruct {
+    pub fn new(name: &str, members: HashMap<String, Object>) -> Self {
+        ModuleStruct {
+            name: name.to_string(),
+            members,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Object> {
+        self.members.get(name).cloned()
+    }
+
+    pub fn member_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.members.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
@@ -1,7 +1,10 @@
 use crate::callable::*;
 use crate::errors::*;
+use crate::instance::*;
 use crate::interpreter::*;
+use crate::map::*;
 use crate::tokens::*;
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::fmt;
 use std::time::SystemTime;
@@ -26,7 +29,7 @@ impl fmt::Debug for Native {
 pub struct NativeClock {}
 
 impl CallableTrait for NativeClock {
-    fn call(&self, _terp: &Interpreter, _args: &Vec<Object>, _class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
+    fn call(&self, _terp: &Interpreter, _args: &[Object], _class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
         match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Ok(n) => Ok(Object::Num(n.as_millis() as f64)),
             Err(_) => Err(Error::system_error("Failed to get time.")),
@@ -41,3 +44,197 @@ impl CallableTrait for NativeClock {
         "Native::Clock".to_string()
     }
 }
+
+/// A native backed by a plain Rust function. Most natives need no state of
+/// their own, so this saves declaring a struct for each of them.
+pub struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    variadic: bool,
+    keywords: &'static [&'static str],
+    function: fn(&Interpreter, &[Object]) -> Result<Object, Error>,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, function: fn(&Interpreter, &[Object]) -> Result<Object, Error>) -> Self {
+        NativeFunction { name, arity, variadic: false, keywords: &[], function }
+    }
+
+    /// A native that accepts `min_arity` or more arguments.
+    pub fn variadic(name: &'static str, min_arity: usize, function: fn(&Interpreter, &[Object]) -> Result<Object, Error>) -> Self {
+        NativeFunction { name, arity: min_arity, variadic: true, keywords: &[], function }
+    }
+
+    pub fn with_keywords(mut self, keywords: &'static [&'static str]) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn into_object(self) -> Object {
+        Object::Native(Rc::new(Native { func: Rc::new(self) }))
+    }
+}
+
+impl CallableTrait for NativeFunction {
+    fn call(&self, interpreter: &Interpreter, arguments: &[Object], _class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
+        (self.function)(interpreter, arguments)
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
+    fn keywords(&self) -> &[&'static str] {
+        self.keywords
+    }
+
+    fn stringify(&self) -> String {
+        format!("Native::{}", self.name)
+    }
+}
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("len", 1, native_len),
+        NativeFunction::new("push", 2, native_push),
+        NativeFunction::new("keys", 1, native_keys),
+        NativeFunction::new("values", 1, native_values),
+        NativeFunction::new("has", 2, native_has),
+        NativeFunction::new("delete", 2, native_delete),
+        NativeFunction::variadic("print", 0, native_print).with_keywords(&["sep", "end"]),
+    ]
+}
+
+pub fn string_arg<'a>(value: &'a Object, native: &str) -> Result<&'a str, Error> {
+    match value {
+        Object::Str(s) => Ok(s),
+        _ => Err(Error::call_error(&format!("{native}() expects a string."))),
+    }
+}
+
+pub fn number_arg(value: &Object, native: &str) -> Result<f64, Error> {
+    match value {
+        Object::Num(n) => Ok(*n),
+        _ => Err(Error::call_error(&format!("{native}() expects a number."))),
+    }
+}
+
+// The largest whole number below which every whole number can be held.
+// Past it numbers skip integers, so a count there can't be trusted.
+const MAX_COUNT: f64 = 9_007_199_254_740_991.0;
+
+/// A non-negative whole number, as used for indexes, counts and lengths.
+pub fn count_arg(value: &Object, native: &str) -> Result<usize, Error> {
+    match value {
+        Object::Num(n) if n.fract() == 0.0 && *n >= 0.0 => {
+            match usize::try_from(*n as u64) {
+                Ok(count) if *n <= MAX_COUNT => Ok(count),
+                _ => Err(Error::call_error(&format!(
+                    "{native}() argument {n} is too large."
+                ))),
+            }
+        }
+        _ => Err(Error::call_error(&format!(
+            "{native}() expects a non-negative integer."
+        ))),
+    }
+}
+
+/// An instance of a member-less class holding `fields`, for natives that
+/// return several named values.
+pub fn record(interpreter: &Interpreter, class_name: &str, fields: Vec<(&str, Object)>) -> Result<Object, Error> {
+    let members = ClassMembers {
+        fields: fields.iter().map(|(name, _)| name.to_string()).collect(),
+        ..Default::default()
+    };
+    let class = Rc::new(ClassStruct::new(
+        interpreter.new_class_id(),
+        class_name.to_string(),
+        None,
+        Vec::new(),
+        Vec::new(),
+        members,
+    ));
+    let instance = InstanceStruct::new(class);
+    for (name, value) in fields {
+        let name = Token::new(TokenKind::Identifier, name.into(), None, 0);
+        instance.set(&name, value, &instance, interpreter)?;
+    }
+    Ok(Object::Instance(instance))
+}
+
+fn native_len(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::List(list) => Ok(Object::Num(list.borrow().len() as f64)),
+        Object::Map(map) => Ok(Object::Num(map.borrow().len() as f64)),
+        Object::Str(s) => Ok(Object::Num(s.chars().count() as f64)),
+        _ => Err(Error::call_error("len() expects a list, map or string.")),
+    }
+}
+
+fn native_push(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::List(list) => {
+            list.borrow_mut().push(args[1].clone());
+            Ok(Object::Nil)
+        }
+        _ => Err(Error::call_error("push() expects a list.")),
+    }
+}
+
+fn map_arg(value: &Object, native: &str) -> Result<Rc<RefCell<MapStruct>>, Error> {
+    match value {
+        Object::Map(map) => Ok(Rc::clone(map)),
+        _ => Err(Error::call_error(&format!("{native}() expects a map."))),
+    }
+}
+
+fn native_keys(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let map = map_arg(&args[0], "keys")?;
+    let keys = map.borrow().entries().iter().map(|(key, _)| Object::Str(key.clone())).collect();
+    Ok(Object::list(keys))
+}
+
+fn native_values(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let map = map_arg(&args[0], "values")?;
+    let values = map.borrow().entries().iter().map(|(_, value)| value.clone()).collect();
+    Ok(Object::list(values))
+}
+
+fn native_has(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let map = map_arg(&args[0], "has")?;
+    let key = string_arg(&args[1], "has")?;
+    let found = map.borrow().contains(key);
+    Ok(Object::Bool(found))
+}
+
+// Returns the removed value, or nil if the key was not there.
+fn native_delete(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let map = map_arg(&args[0], "delete")?;
+    let key = string_arg(&args[1], "delete")?;
+    let removed = map.borrow_mut().remove(key);
+    Ok(removed.unwrap_or(Object::Nil))
+}
+
+fn native_print(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let (values, options) = args.split_at(args.len() - 2);
+    let option = |value: &Object, default: &str| match value {
+        Object::Nil => Ok(default.to_string()),
+        Object::Str(s) => Ok(s.to_string()),
+        _ => Err(Error::call_error("print() expects 'sep' and 'end' to be strings.")),
+    };
+    let sep = option(&options[0], " ")?;
+    let end = option(&options[1], "\n")?;
+
+    let text: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    terp.write_output(&format!("{}{}", text.join(&sep), end))?;
+    Ok(Object::Nil)
+}
@@ -0,0 +1,462 @@
+use crate::errors::*;
+use crate::expr::*;
+use crate::interpreter::Interpreter;
+use crate::stmt::*;
+use crate::tokens::*;
+use std::cell::Cell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// Simplifies a resolved program before it runs: folds operators on
+/// constants, drops branches and loops whose condition is a constant,
+/// drops statements after a `return`, and unwraps parentheses.
+///
+/// Nothing that could fail or have an effect is folded, so a program
+/// reports the same runtime errors at the same tokens as it would have.
+/// `1 / 0` stays a division and still fails at its `/`. Nodes the resolver
+/// annotated keep their slots.
+pub struct Optimizer;
+
+impl StmtVisitor<Option<Rc<Stmt>>> for Optimizer {
+    fn visit_block_stmt(&self, _: Rc<Stmt>, stmt: &BlockStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Block(Rc::new(BlockStmt {
+            statements: self.optimize(&stmt.statements),
+        })))))
+    }
+
+    fn visit_class_stmt(&self, _: Rc<Stmt>, stmt: &ClassStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Class(Rc::new(ClassStmt {
+            name: stmt.name.clone(),
+            superclass: stmt.superclass.clone(),
+            traits: Rc::clone(&stmt.traits),
+            interfaces: Rc::clone(&stmt.interfaces),
+            fields: self.optimize(&stmt.fields),
+            methods: self.optimize(&stmt.methods),
+            getters: self.optimize(&stmt.getters),
+            setters: self.optimize(&stmt.setters),
+            abstract_methods: Rc::clone(&stmt.abstract_methods),
+        })))))
+    }
+
+    fn visit_expression_stmt(&self, _: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Expression(Rc::new(ExpressionStmt {
+            expression: self.optimize_expr(stmt.expression.clone()),
+        })))))
+    }
+
+    fn visit_function_stmt(&self, _: Rc<Stmt>, stmt: &FunctionStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+            name: stmt.name.clone(),
+            params: Rc::clone(&stmt.params),
+            body: self.optimize(&stmt.body),
+        })))))
+    }
+
+    fn visit_if_stmt(&self, _: Rc<Stmt>, stmt: &IfStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        let condition = self.optimize_expr(stmt.condition.clone());
+        let then_branch = self.optimize_stmt(stmt.then_branch.clone());
+        let else_branch = stmt.else_branch.clone().and_then(|branch| self.optimize_stmt(branch));
+        if let Some(value) = constant(&condition) {
+            return Ok(if Interpreter::truthy(value) {
+                then_branch
+            } else {
+                else_branch
+            });
+        }
+        Ok(Some(Rc::new(Stmt::If(Rc::new(IfStmt {
+            condition,
+            then_branch: then_branch.unwrap_or_else(empty),
+            else_branch,
+        })))))
+    }
+
+    fn visit_interface_stmt(&self, wrapper: Rc<Stmt>, _: &InterfaceStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(wrapper))
+    }
+
+    fn visit_print_stmt(&self, _: Rc<Stmt>, stmt: &PrintStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Print(Rc::new(PrintStmt {
+            expression: self.optimize_expr(stmt.expression.clone()),
+        })))))
+    }
+
+    fn visit_return_stmt(&self, _: Rc<Stmt>, stmt: &ReturnStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Return(Rc::new(ReturnStmt {
+            keyword: stmt.keyword.clone(),
+            value: stmt.value.clone().map(|value| self.optimize_expr(value)),
+        })))))
+    }
+
+    fn visit_trait_stmt(&self, _: Rc<Stmt>, stmt: &TraitStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Trait(Rc::new(TraitStmt {
+            name: stmt.name.clone(),
+            methods: self.optimize(&stmt.methods),
+            required: Rc::clone(&stmt.required),
+        })))))
+    }
+
+    fn visit_var_stmt(&self, _: Rc<Stmt>, stmt: &VarStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        Ok(Some(Rc::new(Stmt::Var(Rc::new(VarStmt {
+            name: stmt.name.clone(),
+            initializer: stmt.initializer.clone().map(|initializer| self.optimize_expr(initializer)),
+        })))))
+    }
+
+    fn visit_while_stmt(&self, _: Rc<Stmt>, stmt: &WhileStmt) -> Result<Option<Rc<Stmt>>, Error> {
+        let condition = self.optimize_expr(stmt.condition.clone());
+        if constant(&condition).is_some_and(|value| !Interpreter::truthy(value)) {
+            return Ok(None);
+        }
+        Ok(Some(Rc::new(Stmt::While(Rc::new(WhileStmt {
+            condition,
+            body: self.optimize_stmt(stmt.body.clone()).unwrap_or_else(empty),
+        })))))
+    }
+}
+
+impl ExprVisitor<Rc<Expr>> for Optimizer {
+    fn visit_assign_expr(&self, _: Rc<Expr>, expr: &AssignExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Assign(Rc::new(AssignExpr {
+            name: expr.name.clone(),
+            value: self.optimize_expr(expr.value.clone()),
+            slot: Cell::new(expr.slot.get()),
+        }))))
+    }
+
+    fn visit_binary_expr(&self, _: Rc<Expr>, expr: &BinaryExpr) -> Result<Rc<Expr>, Error> {
+        let left = self.optimize_expr(expr.left.clone());
+        let right = self.optimize_expr(expr.right.clone());
+        if let (Some(a), Some(b)) = (constant(&left), constant(&right)) {
+            let value = Interpreter::operate(a.clone(), &expr.operator.kind, b.clone());
+            // Left for the interpreter to report where it happens.
+            if value != Object::ArithmeticError {
+                return Ok(literal(value));
+            }
+        }
+        Ok(Rc::new(Expr::Binary(Rc::new(BinaryExpr {
+            left,
+            operator: expr.operator.clone(),
+            right,
+        }))))
+    }
+
+    fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Call(Rc::new(CallExpr {
+            callee: self.optimize_expr(expr.callee.clone()),
+            paren: expr.paren.clone(),
+            arguments: self.optimize_exprs(&expr.arguments),
+            keywords: expr.keywords.clone(),
+            keyword_values: self.optimize_exprs(&expr.keyword_values),
+            tail: Cell::new(expr.tail.get()),
+        }))))
+    }
+
+    fn visit_get_expr(&self, _: Rc<Expr>, expr: &GetExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Get(Rc::new(GetExpr {
+            object: self.optimize_expr(expr.object.clone()),
+            name: expr.name.clone(),
+        }))))
+    }
+
+    fn visit_grouping_expr(&self, _: Rc<Expr>, expr: &GroupingExpr) -> Result<Rc<Expr>, Error> {
+        Ok(self.optimize_expr(expr.expression.clone()))
+    }
+
+    fn visit_index_expr(&self, _: Rc<Expr>, expr: &IndexExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Index(Rc::new(IndexExpr {
+            object: self.optimize_expr(expr.object.clone()),
+            bracket: expr.bracket.clone(),
+            index: self.optimize_expr(expr.index.clone()),
+        }))))
+    }
+
+    fn visit_list_expr(&self, _: Rc<Expr>, expr: &ListExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::List(Rc::new(ListExpr {
+            bracket: expr.bracket.clone(),
+            elements: self.optimize_exprs(&expr.elements),
+        }))))
+    }
+
+    fn visit_literal_expr(&self, wrapper: Rc<Expr>, _: &LiteralExpr) -> Result<Rc<Expr>, Error> {
+        Ok(wrapper)
+    }
+
+    // `and` and `or` give one of their operands, so a constant left one
+    // decides which.
+    fn visit_logical_expr(&self, _: Rc<Expr>, expr: &LogicalExpr) -> Result<Rc<Expr>, Error> {
+        let left = self.optimize_expr(expr.left.clone());
+        let right = self.optimize_expr(expr.right.clone());
+        if let Some(value) = constant(&left) {
+            let short_circuits = Interpreter::truthy(value) == (expr.operator.kind == TokenKind::Or);
+            return Ok(if short_circuits { left } else { right });
+        }
+        Ok(Rc::new(Expr::Logical(Rc::new(LogicalExpr {
+            left,
+            operator: expr.operator.clone(),
+            right,
+        }))))
+    }
+
+    fn visit_map_expr(&self, _: Rc<Expr>, expr: &MapExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Map(Rc::new(MapExpr {
+            brace: expr.brace.clone(),
+            keys: self.optimize_exprs(&expr.keys),
+            values: self.optimize_exprs(&expr.values),
+        }))))
+    }
+
+    fn visit_set_expr(&self, _: Rc<Expr>, expr: &SetExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::Set(Rc::new(SetExpr {
+            object: self.optimize_expr(expr.object.clone()),
+            name: expr.name.clone(),
+            value: self.optimize_expr(expr.value.clone()),
+        }))))
+    }
+
+    fn visit_setindex_expr(&self, _: Rc<Expr>, expr: &SetIndexExpr) -> Result<Rc<Expr>, Error> {
+        Ok(Rc::new(Expr::SetIndex(Rc::new(SetIndexExpr {
+            object: self.optimize_expr(expr.object.clone()),
+            bracket: expr.bracket.clone(),
+            index: self.optimize_expr(expr.index.clone()),
+            value: self.optimize_expr(expr.value.clone()),
+        }))))
+    }
+
+    fn visit_super_expr(&self, wrapper: Rc<Expr>, _: &SuperExpr) -> Result<Rc<Expr>, Error> {
+        Ok(wrapper)
+    }
+
+    fn visit_this_expr(&self, wrapper: Rc<Expr>, _: &ThisExpr) -> Result<Rc<Expr>, Error> {
+        Ok(wrapper)
+    }
+
+    fn visit_unary_expr(&self, _: Rc<Expr>, expr: &UnaryExpr) -> Result<Rc<Expr>, Error> {
+        let right = self.optimize_expr(expr.right.clone());
+        match (&expr.operator.kind, constant(&right)) {
+            (TokenKind::Minus, Some(Object::Num(x))) => return Ok(literal(Object::Num(-x))),
+            (TokenKind::Bang, Some(value)) => return Ok(literal(Object::Bool(!Interpreter::truthy(value)))),
+            _ => {}
+        }
+        Ok(Rc::new(Expr::Unary(Rc::new(UnaryExpr {
+            operator: expr.operator.clone(),
+            right,
+        }))))
+    }
+
+    fn visit_variable_expr(&self, wrapper: Rc<Expr>, _: &VariableExpr) -> Result<Rc<Expr>, Error> {
+        Ok(wrapper)
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer
+    }
+
+    /// The optimized version of `statements`. Those after a `return` can't
+    /// run and are dropped.
+    pub fn optimize(&self, statements: &Rc<Vec<Rc<Stmt>>>) -> Rc<Vec<Rc<Stmt>>> {
+        let mut optimized = Vec::new();
+        for stmt in statements.iter() {
+            optimized.extend(self.optimize_stmt(stmt.clone()));
+            if let Stmt::Return(_) = stmt.deref() {
+                break;
+            }
+        }
+        Rc::new(optimized)
+    }
+
+    // None if the statement can be left out. Optimizing can't fail, the
+    // visitors only return results because the traits do.
+    fn optimize_stmt(&self, stmt: Rc<Stmt>) -> Option<Rc<Stmt>> {
+        stmt.accept(stmt.clone(), self).unwrap_or(Some(stmt))
+    }
+
+    fn optimize_expr(&self, expr: Rc<Expr>) -> Rc<Expr> {
+        expr.accept(expr.clone(), self).unwrap_or(expr)
+    }
+
+    fn optimize_exprs(&self, exprs: &[Rc<Expr>]) -> Vec<Rc<Expr>> {
+        exprs.iter().map(|expr| self.optimize_expr(expr.clone())).collect()
+    }
+}
+
+// The value of an expression that is a literal.
+fn constant(expr: &Expr) -> Option<&Object> {
+    match expr {
+        Expr::Literal(literal) => Some(literal.value.as_ref().unwrap_or(&Object::Nil)),
+        _ => None,
+    }
+}
+
+fn literal(value: Object) -> Rc<Expr> {
+    Rc::new(Expr::Literal(Rc::new(LiteralExpr { value: Some(value) })))
+}
+
+// Stands in for a branch or loop body that was optimized away.
+fn empty() -> Rc<Stmt> {
+    Rc::new(Stmt::Block(Rc::new(BlockStmt {
+        statements: Rc::new(Vec::new()),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tests::Buffer;
+    use crate::{resolve, run, Backend};
+
+    // What a program printed, and the error it stopped with.
+    fn outcome(program: &Rc<Vec<Rc<Stmt>>>, backend: Backend) -> (String, Result<(), Option<Error>>) {
+        let interpreter = Interpreter::new();
+        let buffer = Buffer::default();
+        interpreter.set_output(Box::new(buffer.clone()));
+        let result = run(&interpreter, backend, program);
+        (buffer.text(), result)
+    }
+
+    // Runs `source` as written and optimized, on both backends, and checks
+    // that it does the same each time. Returns what it printed.
+    fn same_when_optimized(source: &str) -> (String, Result<(), Option<Error>>) {
+        let program = resolve(source).unwrap();
+        let optimized = Optimizer::new().optimize(&program);
+        let expected = outcome(&program, Backend::TreeWalker);
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            assert_eq!(outcome(&program, backend), expected, "{source}");
+            assert_eq!(outcome(&optimized, backend), expected, "{source}");
+        }
+        expected
+    }
+
+    fn error_at(result: Result<(), Option<Error>>) -> (usize, String, String) {
+        match result {
+            Err(Some(Error::RuntimeError { token, message })) => (token.line, token.lexeme.to_string(), message),
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn folds_constants() {
+        let program = resolve("print 1 + 2 * 3;").unwrap();
+        let optimized = Optimizer::new().optimize(&program);
+        let Stmt::Print(print) = optimized[0].deref() else {
+            panic!("expected a print statement");
+        };
+        assert_eq!(constant(&print.expression), Some(&Object::Num(7.0)));
+
+        let source = r#"
+            print 1 + 2 * 3;
+            print "a" + "b";
+            print -(2 - 5);
+            print !nil;
+            print 1 < 2 and "x";
+            print nil or "default";
+            print (1 == 1) == !false;
+        "#;
+        let (printed, result) = same_when_optimized(source);
+        assert_eq!(printed, "7\nab\n3\ntrue\nx\ndefault\ntrue\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn drops_dead_code() {
+        let program = resolve("if (false) { print 1; } while (false) {} print 2; ").unwrap();
+        assert_eq!(Optimizer::new().optimize(&program).len(), 1);
+    }
+
+    #[test]
+    fn dead_branches_that_declare_variables_keep_slots() {
+        let source = r#"
+            var out = "";
+            {
+                var a = "a";
+                if (false) {
+                    var b = "b";
+                    out = out + b;
+                }
+                var c = "c";
+                fn show() { return a + c; }
+                out = out + show();
+                while (false) { var d = "d"; out = out + d; }
+                if (true) { var e = "e"; out = out + e; } else { var f = "f"; out = out + f; }
+                if (nil) { var g = "g"; out = out + g; } else { var h = "h"; out = out + h + a; }
+                var i = "i";
+                out = out + i + c;
+            }
+            print out;
+        "#;
+        let (printed, result) = same_when_optimized(source);
+        assert_eq!(printed, "acehaic\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn code_after_return_that_declares_variables() {
+        let source = "
+            fn f() {
+                var a = 1;
+                fn g() { return a; }
+                return g() + 1;
+                var b = 2;
+                print b;
+            }
+            print f();
+            fn h(x) {
+                if (x) {
+                    var y = x;
+                    return y;
+                    var z = 3;
+                }
+                var w = 4;
+                return w;
+            }
+            print h(5);
+            print h(false);
+        ";
+        let (printed, result) = same_when_optimized(source);
+        assert_eq!(printed, "2\n5\n4\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn division_by_zero_fails_where_it_is_written() {
+        let (printed, result) = same_when_optimized("print \"before\";\nprint 2 * (1 / 0);");
+        assert_eq!(printed, "before\n");
+        assert_eq!(error_at(result), (2, "/".to_string(), "Invalid binary operator".to_string()));
+    }
+
+    #[test]
+    fn invalid_operands_fail_where_they_are_written() {
+        for (source, operator) in [
+            ("print 1;\nprint \"a\" - 1;", "-"),
+            ("print 1;\nprint nil + 1;", "+"),
+            ("print 1;\nprint true * 2;", "*"),
+        ] {
+            let (printed, result) = same_when_optimized(source);
+            assert_eq!(printed, "1\n");
+            let (line, lexeme, _) = error_at(result);
+            assert_eq!((line, lexeme.as_str()), (2, operator), "{source}");
+        }
+    }
+
+    #[test]
+    fn folds_concatenation_with_numbers() {
+        let (printed, result) = same_when_optimized("print \"a\" + 1;");
+        assert_eq!(printed, "a1\n");
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn keeps_effects_in_folded_logic() {
+        let source = r#"
+            var calls = 0;
+            fn touch() { calls = calls + 1; return calls; }
+            print true and touch();
+            print false or touch();
+            print nil and touch();
+            print calls;
+        "#;
+        let (printed, _) = same_when_optimized(source);
+        assert_eq!(printed, "1\n2\nnil\n2\n");
+    }
+}
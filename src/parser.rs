@@ -1,7 +1,9 @@
 use crate::errors::*;
 use crate::expr::*;
+use crate::interner::intern;
 use crate::stmt::*;
 use crate::tokens::*;
+use std::cell::Cell;
 use std::rc::Rc;
 
 pub struct Parser {
@@ -29,7 +31,11 @@ impl Parser {
     fn declaration(&mut self) -> Result<Rc<Stmt>, Error> {
         let result = if self.match_token(vec![TokenKind::Class]){
             self.class_declaration() 
-        }else if self.match_token(vec![TokenKind::Fn]) {
+        } else if self.match_token(vec![TokenKind::Trait]) {
+            self.trait_declaration()
+        } else if self.match_token(vec![TokenKind::Interface]) {
+            self.interface_declaration()
+        } else if self.match_token(vec![TokenKind::Fn]) {
             self.function("function")
         } else if self.match_token(vec![TokenKind::Var]) {
             self.var_declaration()
@@ -51,25 +57,220 @@ impl Parser {
             self.consume(TokenKind::Identifier, "Expect superclass name.")?;
             Some(Rc::new(Expr::Variable(Rc::new(VariableExpr {
                 name: self.previous(),
+                slot: Cell::new(None),
             }))))
         } else {
             None
         };
 
+        let traits = if self.match_token(vec![TokenKind::With]) {
+            self.name_list("Expect trait name.")?
+        } else {
+            Vec::new()
+        };
+
+        let interfaces = if self.match_token(vec![TokenKind::Implements]) {
+            self.name_list("Expect interface name.")?
+        } else {
+            Vec::new()
+        };
+
+        let mut fields = Vec::new();
         let mut methods = Vec::new();
+        let mut getters = Vec::new();
+        let mut setters = Vec::new();
+        let mut abstract_methods = Vec::new();
         self.consume(TokenKind::LeftBrace, "Expect '{' before class body.")?;
         while !self.check(TokenKind::RightBrace) && !self.is_at_end() {
-            methods.push(self.function("method")?);
+            if self.match_token(vec![TokenKind::Var]) {
+                fields.push(self.field_declaration()?);
+            } else if self.check_modifier("get") {
+                self.advance();
+                getters.push(self.getter()?);
+            } else if self.check_modifier("set") {
+                self.advance();
+                setters.push(self.setter()?);
+            } else if self.check_modifier("abstract") {
+                self.advance();
+                abstract_methods.push(self.abstract_method()?);
+            } else {
+                methods.push(self.function("method")?);
+            }
         }
         self.consume(TokenKind::RightBrace, "Expect '}' after class body.")?;
 
         Ok(Rc::new(Stmt::Class(Rc::new(ClassStmt {
             name,
             superclass,
+            traits: Rc::new(traits),
+            interfaces: Rc::new(interfaces),
+            fields: Rc::new(fields),
             methods: Rc::new(methods),
+            getters: Rc::new(getters),
+            setters: Rc::new(setters),
+            abstract_methods: Rc::new(abstract_methods),
         }))))
     }
 
+    // `var count = 0;` in a class body becomes `this.count = 0;`, which the
+    // class runs against every new instance before `init`.
+    fn field_declaration(&mut self) -> Result<Rc<Stmt>, Error> {
+        let name = self.member_name("Expect field name.")?;
+
+        let value = if self.match_token(vec![TokenKind::Equal]) {
+            Rc::new(self.expression()?)
+        } else {
+            Rc::new(Expr::Literal(Rc::new(LiteralExpr {
+                value: Some(Object::Nil),
+            })))
+        };
+
+        self.consume(TokenKind::Semicolon, "Expect ';' after field declaration.")?;
+
+        let keyword = Token::new(TokenKind::This, intern("this"), None, name.line);
+        Ok(Rc::new(Stmt::Expression(Rc::new(ExpressionStmt {
+            expression: Rc::new(Expr::Set(Rc::new(SetExpr {
+                object: Rc::new(Expr::This(Rc::new(ThisExpr {
+                    keyword,
+                    slot: Cell::new(None),
+                }))),
+                name,
+                value,
+            }))),
+        }))))
+    }
+
+    fn name_list(&mut self, message: &str) -> Result<Vec<Rc<Expr>>, Error> {
+        let mut names = Vec::new();
+        loop {
+            self.consume(TokenKind::Identifier, message)?;
+            names.push(Rc::new(Expr::Variable(Rc::new(VariableExpr {
+                name: self.previous(),
+                slot: Cell::new(None),
+            }))));
+            if !self.match_token(vec![TokenKind::Comma]) {
+                break;
+            }
+        }
+        Ok(names)
+    }
+
+    // A method signature without a body, as used by abstract methods,
+    // required trait methods and interfaces.
+    fn signature(&mut self, name: Token) -> Result<Rc<Stmt>, Error> {
+        self.consume(TokenKind::LeftParen, "Expect '(' after method name")?;
+        let params = Rc::new(self.parameters()?);
+        self.consume(TokenKind::Semicolon, "Expect ';' after method signature.")?;
+        Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+            name,
+            params,
+            body: Rc::new(Vec::new()),
+        }))))
+    }
+
+    fn abstract_method(&mut self) -> Result<Rc<Stmt>, Error> {
+        let name = self.member_name("Expect method name")?;
+        if name.is_private() {
+            return Err(Error::parse_error(&name, "Private methods cannot be abstract."));
+        }
+        self.signature(name)
+    }
+
+    fn interface_declaration(&mut self) -> Result<Rc<Stmt>, Error> {
+        let name = self.consume(TokenKind::Identifier, "Expect interface name.")?;
+
+        let mut methods = Vec::new();
+        self.consume(TokenKind::LeftBrace, "Expect '{' before interface body.")?;
+        while !self.check(TokenKind::RightBrace) && !self.is_at_end() {
+            let method = self.consume(TokenKind::Identifier, "Expect method name")?;
+            methods.push(self.signature(method)?);
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after interface body.")?;
+
+        Ok(Rc::new(Stmt::Interface(Rc::new(InterfaceStmt {
+            name,
+            methods: Rc::new(methods),
+        }))))
+    }
+
+    fn trait_declaration(&mut self) -> Result<Rc<Stmt>, Error> {
+        let name = self.consume(TokenKind::Identifier, "Expect trait name.")?;
+
+        let mut methods = Vec::new();
+        let mut required = Vec::new();
+        self.consume(TokenKind::LeftBrace, "Expect '{' before trait body.")?;
+        while !self.check(TokenKind::RightBrace) && !self.is_at_end() {
+            let name = self.member_name("Expect method name")?;
+            self.consume(TokenKind::LeftParen, "Expect '(' after method name")?;
+            let params = Rc::new(self.parameters()?);
+
+            // A method without a body is one the implementing class must provide.
+            if self.match_token(vec![TokenKind::Semicolon]) {
+                required.push(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+                    name,
+                    params,
+                    body: Rc::new(Vec::new()),
+                }))));
+            } else {
+                self.consume(TokenKind::LeftBrace, "Expect '{' or ';' after method parameters")?;
+                let body = Rc::new(self.block()?);
+                methods.push(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+                    name,
+                    params,
+                    body,
+                }))));
+            }
+        }
+        self.consume(TokenKind::RightBrace, "Expect '}' after trait body.")?;
+
+        Ok(Rc::new(Stmt::Trait(Rc::new(TraitStmt {
+            name,
+            methods: Rc::new(methods),
+            required: Rc::new(required),
+        }))))
+    }
+
+    // `get`, `set` and `abstract` are only keywords when followed by a name,
+    // so methods called `get(...)` or `set(...)` keep working.
+    fn check_modifier(&self, keyword: &str) -> bool {
+        self.check(TokenKind::Identifier)
+            && self.peek().lexeme == keyword
+            && (self.check_next(TokenKind::Identifier) || self.check_next(TokenKind::PrivateIdentifier))
+    }
+
+    fn member_name(&mut self, message: &str) -> Result<Token, Error> {
+        if self.match_token(vec![TokenKind::Identifier, TokenKind::PrivateIdentifier]) {
+            Ok(self.previous())
+        } else {
+            Err(Error::parse_error(&self.peek(), message))
+        }
+    }
+
+    fn getter(&mut self) -> Result<Rc<Stmt>, Error> {
+        let name = self.member_name("Expect getter name")?;
+        self.consume(TokenKind::LeftBrace, "Expect '{' before getter body")?;
+
+        let body = Rc::new(self.block()?);
+        Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+            name,
+            params: Rc::new(Vec::new()),
+            body,
+        }))))
+    }
+
+    fn setter(&mut self) -> Result<Rc<Stmt>, Error> {
+        let setter = self.function("setter")?;
+        if let Stmt::Function(func) = setter.as_ref() {
+            if func.params.len() != 1 {
+                return Err(Error::parse_error(
+                    &func.name,
+                    "Setter must take exactly one parameter.",
+                ));
+            }
+        }
+        Ok(setter)
+    }
+
     fn statement(&mut self) -> Result<Rc<Stmt>, Error> {
         if self.match_token(vec![TokenKind::For]) {
             return self.for_statement();
@@ -78,12 +279,13 @@ impl Parser {
             return Ok(Rc::new(self.if_statement()?));
         }
 
-        if self.match_token(vec![TokenKind::Print]) {
+        if self.check(TokenKind::Print) && !self.is_print_call() {
+            self.advance();
             return Ok(Rc::new(self.print_statement()?));
         }
 
         if self.match_token(vec![TokenKind::Return]) {
-            return Ok(self.return_statement()?);
+            return self.return_statement();
         }
 
         if self.match_token(vec![TokenKind::While]) {
@@ -96,7 +298,7 @@ impl Parser {
             }))));
         }
         
-        Ok(self.expression_statement()?)
+        self.expression_statement()
     }
 
     fn return_statement(&mut self) -> Result<Rc<Stmt>, Error> {
@@ -226,10 +428,25 @@ impl Parser {
     }
 
     fn function(&mut self, kind: &str) -> Result<Rc<Stmt>, Error> {
-        let name = self.consume(TokenKind::Identifier, &format!("Expect {kind} name"))?;
+        let name = if kind == "function" {
+            self.consume(TokenKind::Identifier, &format!("Expect {kind} name"))?
+        } else {
+            self.member_name(&format!("Expect {kind} name"))?
+        };
 
         self.consume(TokenKind::LeftParen, &format!("Expect '(' after {kind} name"))?;
+        let params = self.parameters()?;
+        self.consume(TokenKind::LeftBrace, &format!("Expect '{{' after {kind} body"))?;
+
+        let body = Rc::new(self.block()?);
+        Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
+            name,
+            params: Rc::new(params),
+            body,
+        }))))
+    }
 
+    fn parameters(&mut self) -> Result<Vec<Token>, Error> {
         let mut params = Vec::new();
         if !self.check(TokenKind::RightParen) {
             params.push(self.consume(TokenKind::Identifier, "Expect Parameter Name")?);
@@ -245,14 +462,7 @@ impl Parser {
         }
 
         self.consume(TokenKind::RightParen, "Expect ')' after parameter")?;
-        self.consume(TokenKind::LeftBrace, &format!("Expect '{{' after {kind} body"))?;
-
-        let body = Rc::new(self.block()?);
-        Ok(Rc::new(Stmt::Function(Rc::new(FunctionStmt {
-            name,
-            params: Rc::new(params),
-            body,
-        }))))
+        Ok(params)
     }
 
     fn block(&mut self) -> Result<Vec<Rc<Stmt>>, Error> {
@@ -279,6 +489,7 @@ impl Parser {
                     return Ok(Expr::Assign(Rc::new(AssignExpr {
                         name: v.name.clone(),
                         value: Rc::new(value),
+                        slot: Cell::new(None),
                     })));
                 }
                 Expr::Get(g) => {
@@ -288,6 +499,14 @@ impl Parser {
                         value: Rc::new(value),
                     })));
                 }
+                Expr::Index(i) => {
+                    return Ok(Expr::SetIndex(Rc::new(SetIndexExpr {
+                        object: Rc::clone(&i.object),
+                        bracket: i.bracket.clone(),
+                        index: Rc::clone(&i.index),
+                        value: Rc::new(value),
+                    })));
+                }
                 _ => {
                     return Err(Error::parse_error(
                         &equals,
@@ -411,21 +630,38 @@ impl Parser {
             })));
         }
 
-        Ok(self.call()?)
+        self.call()
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
         let mut arguments: Vec<Rc<Expr>> = Vec::new();
+        let mut keywords: Vec<Token> = Vec::new();
+        let mut keyword_values: Vec<Rc<Expr>> = Vec::new();
 
         if !self.check(TokenKind::RightParen) {
             loop {
-                if arguments.len() >= 255 {
+                if arguments.len() + keywords.len() >= 255 {
                     return Err(Error::parse_error(
                         &self.peek(),
                         "Can't have more than 255 arguments.",
                     ));
                 }
-                arguments.push(Rc::new(self.expression()?));
+                if self.check(TokenKind::Identifier) && self.check_next(TokenKind::Colon) {
+                    let name = self.advance();
+                    if keywords.iter().any(|k| k.lexeme == name.lexeme) {
+                        return Err(Error::parse_error(&name, "Duplicate keyword argument."));
+                    }
+                    self.advance();
+                    keywords.push(name);
+                    keyword_values.push(Rc::new(self.expression()?));
+                } else if !keywords.is_empty() {
+                    return Err(Error::parse_error(
+                        &self.peek(),
+                        "Positional argument follows keyword argument.",
+                    ));
+                } else {
+                    arguments.push(Rc::new(self.expression()?));
+                }
                 if !self.match_token(vec![TokenKind::Comma]) {
                     break;
                 }
@@ -438,6 +674,9 @@ impl Parser {
             callee: Rc::new(callee),
             paren,
             arguments,
+            keywords,
+            keyword_values,
+            tail: Cell::new(false),
         })))
     }
 
@@ -447,8 +686,16 @@ impl Parser {
         loop {
             if self.match_token(vec![TokenKind::LeftParen]) {
                 expr = self.finish_call(expr)?;
+            }else if self.match_token(vec![TokenKind::LeftSquare]) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenKind::RightSquare, "Expect ']' after index.")?;
+                expr = Expr::Index(Rc::new(IndexExpr {
+                    object: Rc::new(expr),
+                    bracket,
+                    index: Rc::new(index),
+                }));
             }else if self.match_token(vec![TokenKind::Dot]) {
-                let name = self.consume(TokenKind::Identifier, "Expect property name after '.'.")?;
+                let name = self.member_name("Expect property name after '.'.")?;
                 expr = Expr::Get(Rc::new(GetExpr {
                     object: Rc::new(expr),
                     name,
@@ -493,6 +740,7 @@ impl Parser {
             return Ok(Expr::Super(Rc::new(SuperExpr{
                 keyword,
                 method,
+                slot: Cell::new(None),
             })))
             
         }
@@ -500,15 +748,59 @@ impl Parser {
         if self.match_token(vec![TokenKind::This]) {
             return Ok(Expr::This(Rc::new(ThisExpr {
                 keyword: self.previous(),
+                slot: Cell::new(None),
             })));
         }
 
         if self.match_token(vec![TokenKind::Identifier]) {
             return Ok(Expr::Variable(Rc::new(VariableExpr {
                 name: self.previous(),
+                slot: Cell::new(None),
+            })));
+        }
+
+        if self.check(TokenKind::Print) && self.check_next(TokenKind::LeftParen) {
+            let mut name = self.advance();
+            name.kind = TokenKind::Identifier;
+            return Ok(Expr::Variable(Rc::new(VariableExpr {
+                name,
+                slot: Cell::new(None),
             })));
         }
 
+        if self.match_token(vec![TokenKind::LeftSquare]) {
+            let bracket = self.previous();
+            let mut elements = Vec::new();
+            if !self.check(TokenKind::RightSquare) {
+                loop {
+                    elements.push(Rc::new(self.expression()?));
+                    if !self.match_token(vec![TokenKind::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenKind::RightSquare, "Expect ']' after list elements.")?;
+            return Ok(Expr::List(Rc::new(ListExpr { bracket, elements })));
+        }
+
+        if self.match_token(vec![TokenKind::LeftBrace]) {
+            let brace = self.previous();
+            let mut keys = Vec::new();
+            let mut values = Vec::new();
+            if !self.check(TokenKind::RightBrace) {
+                loop {
+                    keys.push(Rc::new(self.expression()?));
+                    self.consume(TokenKind::Colon, "Expect ':' after map key.")?;
+                    values.push(Rc::new(self.expression()?));
+                    if !self.match_token(vec![TokenKind::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume(TokenKind::RightBrace, "Expect '}' after map entries.")?;
+            return Ok(Expr::Map(Rc::new(MapExpr { brace, keys, values })));
+        }
+
         if self.match_token(vec![TokenKind::LeftParen]) {
             let expr = self.expression()?;
             self.consume(TokenKind::RightParen, "Expect ')' after expression.")?;
@@ -516,10 +808,7 @@ impl Parser {
                 expression: Rc::new(expr),
             })));
         }
-        
-        if self.match_token(vec![TokenKind::Comment]) {
-            return self.primary();
-        }
+
 
         Err(Error::parse_error(&self.peek(), "Expect expression."))
     }
@@ -541,6 +830,45 @@ impl Parser {
         self.peek().kind == kind
     }
 
+    // Whether the `print` about to be parsed calls the print function. It
+    // does only when a print statement couldn't take what follows: an empty
+    // argument list, several arguments or keyword arguments. So `print (1)
+    // + 2;` still prints 3.
+    fn is_print_call(&self) -> bool {
+        if !self.check_next(TokenKind::LeftParen) {
+            return false;
+        }
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(self.current + 1) {
+            match token.kind {
+                TokenKind::LeftParen | TokenKind::LeftSquare | TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightParen | TokenKind::RightSquare | TokenKind::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i == self.current + 2;
+                    }
+                }
+                TokenKind::Comma if depth == 1 => return true,
+                TokenKind::Identifier
+                    if depth == 1
+                        && self.tokens.get(i + 1).is_some_and(|next| next.kind == TokenKind::Colon) =>
+                {
+                    return true;
+                }
+                TokenKind::EOF => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    fn check_next(&self, kind: TokenKind) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.kind == kind,
+            None => false,
+        }
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -577,6 +905,8 @@ impl Parser {
 
             match self.peek().kind {
                 TokenKind::Class
+                | TokenKind::Trait
+                | TokenKind::Interface
                 | TokenKind::Fn
                 | TokenKind::Var
                 | TokenKind::For
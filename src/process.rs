@@ -0,0 +1,107 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::env;
+use std::process::{self, Command};
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("getenv", 1, native_getenv),
+        NativeFunction::new("setenv", 2, native_setenv),
+        NativeFunction::new("exit", 1, native_exit),
+        NativeFunction::new("cwd", 0, native_cwd),
+        NativeFunction::new("chdir", 1, native_chdir),
+        NativeFunction::variadic("run", 1, native_run),
+        NativeFunction::new("shell", 1, native_shell),
+    ]
+}
+
+fn native_getenv(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let name = string_arg(&args[0], "getenv")?;
+    match env::var(name) {
+        Ok(value) => Ok(Object::Str(value.into())),
+        Err(_) => Ok(Object::Nil),
+    }
+}
+
+// Setting a variable to nil removes it.
+fn native_setenv(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let name = string_arg(&args[0], "setenv")?;
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(Error::call_error(&format!(
+            "setenv() got invalid variable name '{name}'."
+        )));
+    }
+    match &args[1] {
+        Object::Nil => env::remove_var(name),
+        value => {
+            let value = value.to_string();
+            if value.contains('\0') {
+                return Err(Error::call_error(&format!(
+                    "setenv() got a value for '{name}' containing a NUL character."
+                )));
+            }
+            env::set_var(name, value)
+        }
+    }
+    Ok(Object::Nil)
+}
+
+fn native_exit(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let code = match &args[0] {
+        Object::Num(n) if n.fract() == 0.0 && *n >= i32::MIN as f64 && *n <= i32::MAX as f64 => *n as i32,
+        _ => return Err(Error::call_error("exit() expects an integer status code.")),
+    };
+    let _ = terp.flush_output();
+    process::exit(code)
+}
+
+fn native_cwd(_terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    env::current_dir()
+        .map(|dir| Object::Str(dir.to_string_lossy().into_owned().into()))
+        .map_err(|e| Error::call_error(&format!("cwd() failed: {e}.")))
+}
+
+fn native_chdir(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let path = string_arg(&args[0], "chdir")?;
+    env::set_current_dir(path)
+        .map_err(|e| Error::call_error(&format!("chdir() failed for '{path}': {e}.")))?;
+    Ok(Object::Nil)
+}
+
+fn native_run(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let program = string_arg(&args[0], "run")?;
+    let mut command = Command::new(program);
+    command.args(args[1..].iter().map(|arg| arg.to_string()));
+    output(terp, "run", program, command)
+}
+
+fn native_shell(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let line = string_arg(&args[0], "shell")?;
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(line);
+    output(terp, "shell", line, command)
+}
+
+// Runs `command` to completion with its output captured. `status` is nil
+// when the process was ended by a signal.
+fn output(terp: &Interpreter, native: &str, name: &str, mut command: Command) -> Result<Object, Error> {
+    let _ = terp.flush_output();
+    let output = command
+        .output()
+        .map_err(|e| Error::call_error(&format!("{native}() failed to start '{name}': {e}.")))?;
+    let status = match output.status.code() {
+        Some(code) => Object::Num(code as f64),
+        None => Object::Nil,
+    };
+    record(
+        terp,
+        "Process",
+        vec![
+            ("status", status),
+            ("stdout", Object::Str(String::from_utf8_lossy(&output.stdout).into_owned().into())),
+            ("stderr", Object::Str(String::from_utf8_lossy(&output.stderr).into_owned().into())),
+        ],
+    )
+}
@@ -0,0 +1,137 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::time::SystemTime;
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("random", 0, native_random),
+        NativeFunction::new("randint", 2, native_randint),
+        NativeFunction::new("choice", 1, native_choice),
+        NativeFunction::new("shuffle", 1, native_shuffle),
+        NativeFunction::new("seed", 1, native_seed),
+    ]
+}
+
+/// xoshiro256** generator. Not suitable for cryptography, but fast and
+/// reproducible: the same seed always gives the same sequence.
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Spread the seed over the state with splitmix64, which never
+        // leaves it all zero.
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        Rng {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    /// Seeded from the clock, for runs that don't call `seed`.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::new(nanos)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    /// A float in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A uniform integer in [0, n), rejecting the values that would bias
+    /// a plain modulo.
+    fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+fn integer_arg(value: &Object, native: &str) -> Result<i64, Error> {
+    match value {
+        Object::Num(n) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => Ok(*n as i64),
+        _ => Err(Error::call_error(&format!("{native}() expects an integer."))),
+    }
+}
+
+fn native_random(terp: &Interpreter, _args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Num(terp.rng().next_f64()))
+}
+
+// Both ends are included, so randint(1, 6) rolls a die.
+fn native_randint(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let low = integer_arg(&args[0], "randint")?;
+    let high = integer_arg(&args[1], "randint")?;
+    if low > high {
+        return Err(Error::call_error(&format!(
+            "randint() got an empty range {low}..{high}."
+        )));
+    }
+    let offset = terp.rng().below((high - low) as u64 + 1);
+    Ok(Object::Num((low + offset as i64) as f64))
+}
+
+fn native_choice(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::List(list) => {
+            let list = list.borrow();
+            if list.is_empty() {
+                return Err(Error::call_error("choice() of an empty list."));
+            }
+            let i = terp.rng().below(list.len() as u64) as usize;
+            Ok(list[i].clone())
+        }
+        _ => Err(Error::call_error("choice() expects a list.")),
+    }
+}
+
+fn native_shuffle(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::List(list) => {
+            let mut list = list.borrow_mut();
+            let mut rng = terp.rng();
+            for i in (1..list.len()).rev() {
+                let j = rng.below(i as u64 + 1) as usize;
+                list.swap(i, j);
+            }
+            Ok(Object::Nil)
+        }
+        _ => Err(Error::call_error("shuffle() expects a list.")),
+    }
+}
+
+fn native_seed(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let seed = integer_arg(&args[0], "seed")?;
+    *terp.rng() = Rng::new(seed as u64);
+    Ok(Object::Nil)
+}
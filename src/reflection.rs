@@ -0,0 +1,152 @@
+use crate::callable::*;
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("type", 1, native_type),
+        NativeFunction::new("isinstance", 2, native_isinstance),
+        NativeFunction::new("hasattr", 2, native_hasattr),
+        NativeFunction::new("getattr", 2, native_getattr),
+        NativeFunction::new("setattr", 3, native_setattr),
+        NativeFunction::new("fields", 1, native_fields),
+        NativeFunction::new("methods", 1, native_methods),
+        NativeFunction::new("superclass", 1, native_superclass),
+        NativeFunction::new("arity", 1, native_arity),
+    ]
+}
+
+/// Name of a value's type. Instances report the name of their class.
+pub fn type_name(value: &Object) -> String {
+    match value {
+        Object::Num(_) => "number".to_string(),
+        Object::Str(_) => "string".to_string(),
+        Object::Bool(_) => "bool".to_string(),
+        Object::Nil => "nil".to_string(),
+        Object::List(_) => "list".to_string(),
+        Object::Map(_) => "map".to_string(),
+        Object::Function(_) | Object::Native(_) => "function".to_string(),
+        Object::Class(_) => "class".to_string(),
+        Object::Trait(_) => "trait".to_string(),
+        Object::Interface(_) => "interface".to_string(),
+        Object::Module(_) => "module".to_string(),
+        Object::Regex(_) => "regex".to_string(),
+        Object::Instance(i) => i.class.name.clone(),
+        Object::ArithmeticError => "error".to_string(),
+    }
+}
+
+fn property_name(value: &Object, native: &str) -> Result<Token, Error> {
+    match value {
+        Object::Str(name) if name.starts_with('#') => Err(Error::call_error(&format!(
+            "{native}() cannot access private member '{name}'."
+        ))),
+        Object::Str(name) => Ok(Token::new(TokenKind::Identifier, name.clone(), None, 0)),
+        _ => Err(Error::call_error(&format!(
+            "{native}() expects a property name string."
+        ))),
+    }
+}
+
+fn names_list(names: Vec<String>) -> Object {
+    Object::list(names.into_iter().map(|name| Object::Str(name.into())).collect())
+}
+
+fn native_type(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Str(type_name(&args[0]).into()))
+}
+
+fn native_isinstance(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let instance = match &args[0] {
+        Object::Instance(instance) => Some(instance),
+        _ => None,
+    };
+    let result = match &args[1] {
+        Object::Class(class) => instance.is_some_and(|i| i.class.is_subclass_of(class)),
+        Object::Trait(t) => instance.is_some_and(|i| i.class.has_trait(t)),
+        Object::Interface(interface) => instance.is_some_and(|i| i.class.implements(interface)),
+        _ => {
+            return Err(Error::call_error(
+                "isinstance() expects a class, trait or interface.",
+            ))
+        }
+    };
+    Ok(Object::Bool(result))
+}
+
+fn native_hasattr(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match (&args[0], &args[1]) {
+        (_, Object::Str(name)) if name.starts_with('#') => Ok(Object::Bool(false)),
+        (Object::Instance(instance), Object::Str(name)) => Ok(Object::Bool(instance.has(name))),
+        (_, Object::Str(_)) => Ok(Object::Bool(false)),
+        _ => Err(Error::call_error("hasattr() expects a property name string.")),
+    }
+}
+
+fn native_getattr(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let name = property_name(&args[1], "getattr")?;
+    match &args[0] {
+        Object::Instance(instance) if instance.has(&name.lexeme) => instance.get(&name, instance, terp),
+        Object::Instance(_) => Err(Error::call_error(&format!(
+            "Undefined property '{}'.",
+            name.lexeme
+        ))),
+        _ => Err(Error::call_error("Only instances have properties.")),
+    }
+}
+
+fn native_setattr(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let name = property_name(&args[1], "setattr")?;
+    match &args[0] {
+        Object::Instance(instance) => {
+            if instance.class.find_setter(&name.lexeme).is_none()
+                && instance.class.find_getter(&name.lexeme).is_some()
+            {
+                return Err(Error::call_error(&format!(
+                    "Cannot assign to read-only property '{}'.",
+                    name.lexeme
+                )));
+            }
+            instance.set(&name, args[2].clone(), instance, terp)?;
+            Ok(Object::Nil)
+        }
+        _ => Err(Error::call_error("Only instances have fields.")),
+    }
+}
+
+fn native_fields(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::Instance(instance) => Ok(names_list(instance.field_names())),
+        _ => Err(Error::call_error("fields() expects an instance.")),
+    }
+}
+
+fn native_methods(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::Class(class) => Ok(names_list(class.method_names())),
+        Object::Instance(instance) => Ok(names_list(instance.class.method_names())),
+        _ => Err(Error::call_error("methods() expects a class or an instance.")),
+    }
+}
+
+fn native_superclass(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    match &args[0] {
+        Object::Class(class) => Ok(match class.superclass() {
+            Some(superclass) => Object::Class(superclass),
+            None => Object::Nil,
+        }),
+        _ => Err(Error::call_error("superclass() expects a class.")),
+    }
+}
+
+fn native_arity(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let arity = match &args[0] {
+        Object::Function(function) => function.arity(),
+        Object::Native(native) => native.func.arity(),
+        Object::Class(class) => class.arity(),
+        _ => return Err(Error::call_error("arity() expects a function or a class.")),
+    };
+    Ok(Object::Num(arity as f64))
+}
@@ -0,0 +1,740 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::module::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Bounds on `{n,m}` counts and on compiled program size, so a short
+// pattern can't expand into an enormous program.
+const MAX_REPEAT: usize = 1000;
+const MAX_PROGRAM: usize = 100_000;
+
+pub fn module() -> ModuleStruct {
+    let mut members = HashMap::new();
+    for native in [
+        NativeFunction::new("compile", 1, native_compile),
+        NativeFunction::new("match", 2, native_match),
+        NativeFunction::new("search", 2, native_search),
+        NativeFunction::new("findAll", 2, native_find_all),
+        NativeFunction::new("replace", 3, native_replace),
+        NativeFunction::new("split", 2, native_split),
+    ] {
+        members.insert(native.name().to_string(), native.into_object());
+    }
+    ModuleStruct::new("regex", members)
+}
+
+/// A compiled pattern. Matching runs a Pike VM over the program, which
+/// takes time linear in the input whatever the pattern, so untrusted
+/// patterns can't hang a script.
+#[derive(Debug)]
+pub struct Regex {
+    pub source: String,
+    program: Vec<Inst>,
+    groups: usize,
+}
+
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<regex {}>", self.source)
+    }
+}
+
+/// Capture positions, as char offsets: slots 2n and 2n+1 hold the start
+/// and end of group n, with group 0 being the whole match.
+type Captures = Vec<Option<usize>>;
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    /// Try both targets, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Perl::Digit => c.is_ascii_digit(),
+            Perl::Word => is_word(c),
+            Perl::Space => c.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Perl(Perl, bool),
+}
+
+#[derive(Debug, Clone)]
+struct CharClass {
+    negated: bool,
+    items: Vec<ClassItem>,
+}
+
+impl CharClass {
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| match *item {
+            ClassItem::Range(low, high) => low <= c && c <= high,
+            ClassItem::Perl(perl, negated) => perl.matches(c) != negated,
+        });
+        found != self.negated
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any,
+    Class(CharClass),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    current: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at position {}", self.current)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.eat('|') {
+            alternatives.push(self.concat()?);
+        }
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Node::Alternate(alternatives)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            nodes.push(self.repeat()?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repeat(&mut self) -> Result<Node, String> {
+        let node = self.atom()?;
+        let (min, max) = if self.eat('*') {
+            (0, None)
+        } else if self.eat('+') {
+            (1, None)
+        } else if self.eat('?') {
+            (0, Some(1))
+        } else if let Some(counts) = self.counts()? {
+            counts
+        } else {
+            return Ok(node);
+        };
+        let greedy = !self.eat('?');
+        if matches!(self.peek(), Some('*' | '+' | '?')) {
+            return Err(self.error("multiple repeat"));
+        }
+        if matches!(node, Node::Assert(_) | Node::Empty) {
+            return Err(self.error("nothing to repeat"));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    // Parses `{n}`, `{n,}` or `{n,m}`. Anything else is a literal '{'.
+    fn counts(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let start = self.current;
+        if !self.eat('{') {
+            return Ok(None);
+        }
+        let Some(min) = self.number() else {
+            self.current = start;
+            return Ok(None);
+        };
+        let max = if self.eat(',') { self.number() } else { Some(min) };
+        if !self.eat('}') {
+            self.current = start;
+            return Ok(None);
+        }
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return Err(self.error("repeat count too large"));
+        }
+        if max.is_some_and(|max| max < min) {
+            return Err(self.error("repeat range out of order"));
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.current;
+        while matches!(self.peek(), Some('0'..='9')) {
+            self.current += 1;
+        }
+        let digits: String = self.chars[start..self.current].iter().collect();
+        digits.parse().ok()
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end of pattern"))?;
+        self.current += 1;
+        match c {
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(self.error("unknown group type"));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(self.error("missing ')'"));
+                }
+                Ok(Node::Group(Box::new(inner), index))
+            }
+            '[' => self.class(),
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Assert(Assertion::Start)),
+            '$' => Ok(Node::Assert(Assertion::End)),
+            '\\' => self.escape(),
+            '*' | '+' | '?' => {
+                self.current -= 1;
+                Err(self.error("nothing to repeat"))
+            }
+            c => Ok(Node::Char(c)),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or_else(|| self.error("pattern ends with '\\'"))?;
+        self.current += 1;
+        Ok(match c {
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            _ => match self.class_escape(c)? {
+                ClassItem::Range(c, _) => Node::Char(c),
+                item => Node::Class(CharClass {
+                    negated: false,
+                    items: vec![item],
+                }),
+            },
+        })
+    }
+
+    fn class_escape(&mut self, c: char) -> Result<ClassItem, String> {
+        let literal = |c| Ok(ClassItem::Range(c, c));
+        match c {
+            'd' => Ok(ClassItem::Perl(Perl::Digit, false)),
+            'D' => Ok(ClassItem::Perl(Perl::Digit, true)),
+            'w' => Ok(ClassItem::Perl(Perl::Word, false)),
+            'W' => Ok(ClassItem::Perl(Perl::Word, true)),
+            's' => Ok(ClassItem::Perl(Perl::Space, false)),
+            'S' => Ok(ClassItem::Perl(Perl::Space, true)),
+            'n' => literal('\n'),
+            't' => literal('\t'),
+            'r' => literal('\r'),
+            'f' => literal('\u{c}'),
+            'v' => literal('\u{b}'),
+            '0' => literal('\0'),
+            c if c.is_alphanumeric() => {
+                self.current -= 1;
+                Err(self.error(&format!("unknown escape '\\{c}'")))
+            }
+            c => literal(c),
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat('^');
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+            self.current += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let item = if c == '\\' {
+                let escaped = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+                self.current += 1;
+                // Inside a class, \b is a backspace rather than a boundary.
+                if escaped == 'b' {
+                    ClassItem::Range('\u{8}', '\u{8}')
+                } else {
+                    self.class_escape(escaped)?
+                }
+            } else {
+                ClassItem::Range(c, c)
+            };
+            let item = match item {
+                ClassItem::Range(low, _) if self.peek() == Some('-') && self.chars.get(self.current + 1).is_some_and(|&c| c != ']') => {
+                    self.current += 1;
+                    let mut high = self.peek().unwrap();
+                    self.current += 1;
+                    if high == '\\' {
+                        let escaped = self.peek().ok_or_else(|| self.error("missing ']'"))?;
+                        self.current += 1;
+                        high = match self.class_escape(escaped)? {
+                            ClassItem::Range(high, _) => high,
+                            _ => return Err(self.error("invalid class range")),
+                        };
+                    }
+                    if high < low {
+                        return Err(self.error("class range out of order"));
+                    }
+                    ClassItem::Range(low, high)
+                }
+                item => item,
+            };
+            items.push(item);
+        }
+        Ok(Node::Class(CharClass { negated, items }))
+    }
+}
+
+struct Compiler {
+    program: Vec<Inst>,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Result<usize, String> {
+        if self.program.len() >= MAX_PROGRAM {
+            return Err("pattern is too large".to_string());
+        }
+        self.program.push(inst);
+        Ok(self.program.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        match node {
+            Node::Empty => {}
+            Node::Char(c) => {
+                self.emit(Inst::Char(*c))?;
+            }
+            Node::Any => {
+                self.emit(Inst::Any)?;
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()))?;
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion))?;
+            }
+            Node::Group(inner, Some(index)) => {
+                self.emit(Inst::Save(2 * index))?;
+                self.compile(inner)?;
+                self.emit(Inst::Save(2 * index + 1))?;
+            }
+            Node::Group(inner, None) => self.compile(inner)?,
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternate(alternatives) => {
+                let mut jumps = Vec::new();
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i + 1 == alternatives.len() {
+                        self.compile(alternative)?;
+                    } else {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(alternative)?;
+                        jumps.push(self.emit(Inst::Jump(0))?);
+                        self.program[split] = Inst::Split(split + 1, self.program.len());
+                    }
+                }
+                for jump in jumps {
+                    self.program[jump] = Inst::Jump(self.program.len());
+                }
+            }
+            Node::Repeat { node, min, max, greedy } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Inst::Split(0, 0))?;
+                        self.compile(node)?;
+                        self.emit(Inst::Jump(split))?;
+                        self.program[split] = self.split(split + 1, self.program.len(), *greedy);
+                    }
+                    Some(max) => {
+                        for _ in *min..*max {
+                            let split = self.emit(Inst::Split(0, 0))?;
+                            self.compile(node)?;
+                            self.program[split] = self.split(split + 1, self.program.len(), *greedy);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn split(&self, body: usize, skip: usize, greedy: bool) -> Inst {
+        if greedy {
+            Inst::Split(body, skip)
+        } else {
+            Inst::Split(skip, body)
+        }
+    }
+}
+
+struct Thread {
+    pc: usize,
+    captures: Captures,
+}
+
+impl Regex {
+    pub fn new(source: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            current: 0,
+            groups: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.current < parser.chars.len() {
+            return Err(parser.error("unbalanced ')'"));
+        }
+
+        let mut compiler = Compiler { program: Vec::new() };
+        compiler.emit(Inst::Save(0))?;
+        compiler.compile(&node)?;
+        compiler.emit(Inst::Save(1))?;
+        compiler.emit(Inst::Match)?;
+        Ok(Regex {
+            source: source.to_string(),
+            program: compiler.program,
+            groups: parser.groups,
+        })
+    }
+
+    /// The leftmost match starting at or after `start`.
+    fn find_at(&self, input: &[char], start: usize) -> Option<Captures> {
+        self.run(input, start, false)
+    }
+
+    /// A match that covers all of `input`.
+    fn full_match(&self, input: &[char]) -> Option<Captures> {
+        self.run(input, 0, true)
+    }
+
+    fn run(&self, input: &[char], start: usize, full: bool) -> Option<Captures> {
+        let mut current: Vec<Thread> = Vec::new();
+        let mut next: Vec<Thread> = Vec::new();
+        // The position each instruction was last added at, plus one.
+        let mut visited = vec![0; self.program.len()];
+        let mut matched = None;
+
+        for i in start..=input.len() {
+            if matched.is_none() && (!full || i == start) {
+                let captures = vec![None; 2 * (self.groups + 1)];
+                self.add_thread(&mut current, &mut visited, 0, captures, input, i);
+            }
+            if current.is_empty() {
+                if matched.is_some() || full {
+                    break;
+                }
+                continue;
+            }
+
+            for thread in current.drain(..) {
+                let consumed = match &self.program[thread.pc] {
+                    Inst::Char(c) => input.get(i) == Some(c),
+                    Inst::Any => input.get(i).is_some_and(|&c| c != '\n'),
+                    Inst::Class(class) => input.get(i).is_some_and(|&c| class.matches(c)),
+                    Inst::Match => {
+                        if full && i != input.len() {
+                            continue;
+                        }
+                        // Threads after this one have lower priority.
+                        matched = Some(thread.captures);
+                        break;
+                    }
+                    _ => false,
+                };
+                if consumed {
+                    self.add_thread(&mut next, &mut visited, thread.pc + 1, thread.captures, input, i + 1);
+                }
+            }
+            current.clear();
+            std::mem::swap(&mut current, &mut next);
+        }
+        matched
+    }
+
+    // Follows jumps, splits, saves and assertions from `pc`, adding each
+    // thread that reaches a consuming instruction in priority order.
+    fn add_thread(&self, list: &mut Vec<Thread>, visited: &mut [usize], pc: usize, captures: Captures, input: &[char], i: usize) {
+        let mut stack = vec![(pc, captures)];
+        while let Some((pc, mut captures)) = stack.pop() {
+            if visited[pc] == i + 1 {
+                continue;
+            }
+            visited[pc] = i + 1;
+            match self.program[pc] {
+                Inst::Jump(target) => stack.push((target, captures)),
+                Inst::Split(first, second) => {
+                    stack.push((second, captures.clone()));
+                    stack.push((first, captures));
+                }
+                Inst::Save(slot) => {
+                    captures[slot] = Some(i);
+                    stack.push((pc + 1, captures));
+                }
+                Inst::Assert(assertion) => {
+                    if Self::holds(assertion, input, i) {
+                        stack.push((pc + 1, captures));
+                    }
+                }
+                _ => list.push(Thread { pc, captures }),
+            }
+        }
+    }
+
+    fn holds(assertion: Assertion, input: &[char], i: usize) -> bool {
+        let boundary = || {
+            let before = i > 0 && is_word(input[i - 1]);
+            let after = i < input.len() && is_word(input[i]);
+            before != after
+        };
+        match assertion {
+            Assertion::Start => i == 0,
+            Assertion::End => i == input.len(),
+            Assertion::WordBoundary => boundary(),
+            Assertion::NotWordBoundary => !boundary(),
+        }
+    }
+
+    /// Every non-overlapping match, left to right.
+    fn find_all(&self, input: &[char]) -> Vec<Captures> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start <= input.len() {
+            let Some(captures) = self.find_at(input, start) else {
+                break;
+            };
+            let (match_start, match_end) = (captures[0].unwrap(), captures[1].unwrap());
+            // Step past an empty match so the search makes progress.
+            start = if match_end == match_start { match_end + 1 } else { match_end };
+            matches.push(captures);
+        }
+        matches
+    }
+}
+
+fn text(input: &[char], start: usize, end: usize) -> String {
+    input[start..end].iter().collect()
+}
+
+// Accepts a compiled pattern, or compiles a pattern string.
+fn pattern_arg(value: &Object, native: &str) -> Result<Rc<Regex>, Error> {
+    match value {
+        Object::Regex(regex) => Ok(Rc::clone(regex)),
+        Object::Str(source) => Regex::new(source).map(Rc::new).map_err(|message| {
+            Error::call_error(&format!("{native}() got invalid pattern '{source}': {message}."))
+        }),
+        _ => Err(Error::call_error(&format!("{native}() expects a pattern."))),
+    }
+}
+
+fn match_object(terp: &Interpreter, input: &[char], captures: &Captures) -> Result<Object, Error> {
+    let (start, end) = (captures[0].unwrap(), captures[1].unwrap());
+    let groups = captures[2..]
+        .chunks(2)
+        .map(|slots| match (slots[0], slots[1]) {
+            (Some(start), Some(end)) => Object::Str(text(input, start, end).into()),
+            _ => Object::Nil,
+        })
+        .collect();
+    record(
+        terp,
+        "Match",
+        vec![
+            ("text", Object::Str(text(input, start, end).into())),
+            ("start", Object::Num(start as f64)),
+            ("end", Object::Num(end as f64)),
+            ("groups", Object::list(groups)),
+        ],
+    )
+}
+
+fn native_compile(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Regex(pattern_arg(&args[0], "regex.compile")?))
+}
+
+fn native_match(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let regex = pattern_arg(&args[0], "regex.match")?;
+    let input: Vec<char> = string_arg(&args[1], "regex.match")?.chars().collect();
+    match regex.full_match(&input) {
+        Some(captures) => match_object(terp, &input, &captures),
+        None => Ok(Object::Nil),
+    }
+}
+
+fn native_search(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let regex = pattern_arg(&args[0], "regex.search")?;
+    let input: Vec<char> = string_arg(&args[1], "regex.search")?.chars().collect();
+    match regex.find_at(&input, 0) {
+        Some(captures) => match_object(terp, &input, &captures),
+        None => Ok(Object::Nil),
+    }
+}
+
+fn native_find_all(terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let regex = pattern_arg(&args[0], "regex.findAll")?;
+    let input: Vec<char> = string_arg(&args[1], "regex.findAll")?.chars().collect();
+    let mut matches = Vec::new();
+    for captures in regex.find_all(&input) {
+        matches.push(match_object(terp, &input, &captures)?);
+    }
+    Ok(Object::list(matches))
+}
+
+fn native_replace(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let regex = pattern_arg(&args[0], "regex.replace")?;
+    let input: Vec<char> = string_arg(&args[1], "regex.replace")?.chars().collect();
+    let replacement: Vec<char> = string_arg(&args[2], "regex.replace")?.chars().collect();
+
+    let mut out = String::new();
+    let mut last = 0;
+    for captures in regex.find_all(&input) {
+        let (start, end) = (captures[0].unwrap(), captures[1].unwrap());
+        out.push_str(&text(&input, last, start));
+        expand(&mut out, &replacement, &input, &captures, regex.groups)?;
+        last = end;
+    }
+    out.push_str(&text(&input, last, input.len()));
+    Ok(Object::Str(out.into()))
+}
+
+// Writes `replacement` with `$n` replaced by group n and `$$` by '$'.
+fn expand(out: &mut String, replacement: &[char], input: &[char], captures: &Captures, groups: usize) -> Result<(), Error> {
+    let mut i = 0;
+    while i < replacement.len() {
+        let c = replacement[i];
+        i += 1;
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        if replacement.get(i) == Some(&'$') {
+            out.push('$');
+            i += 1;
+            continue;
+        }
+        let digits_start = i;
+        while replacement.get(i).is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
+        }
+        if i == digits_start {
+            out.push('$');
+            continue;
+        }
+        let group: usize = text(replacement, digits_start, i).parse().unwrap_or(usize::MAX);
+        if group > groups {
+            return Err(Error::call_error(&format!(
+                "regex.replace() refers to group {group}, but the pattern has {groups}."
+            )));
+        }
+        if let (Some(start), Some(end)) = (captures[2 * group], captures[2 * group + 1]) {
+            out.push_str(&text(input, start, end));
+        }
+    }
+    Ok(())
+}
+
+// Empty matches don't split, so `split("x*", "abc")` is `["abc"]`.
+fn native_split(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let regex = pattern_arg(&args[0], "regex.split")?;
+    let input: Vec<char> = string_arg(&args[1], "regex.split")?.chars().collect();
+
+    let mut pieces = Vec::new();
+    let mut last = 0;
+    for captures in regex.find_all(&input) {
+        let (start, end) = (captures[0].unwrap(), captures[1].unwrap());
+        if start == end {
+            continue;
+        }
+        pieces.push(Object::Str(text(&input, last, start).into()));
+        last = end;
+    }
+    pieces.push(Object::Str(text(&input, last, input.len()).into()));
+    Ok(Object::list(pieces))
+}
@@ -3,20 +3,27 @@ use std::collections::HashMap;
 use std::rc::Rc;
 use std::ops::Deref;
 
+use crate::enviroment::Slot;
 use crate::errors::*;
+use crate::interner::{intern, RcStr};
 use crate::expr::*;
-use crate::interpreter::*;
 use crate::stmt::*;
 use crate::tokens::*;
 
-pub struct Resolver<'a>{
-    interpreter: &'a Interpreter,
-    scopes: RefCell<Vec<RefCell<HashMap<String, bool>>>>,
+pub struct Resolver {
+    scopes: RefCell<Vec<RefCell<HashMap<RcStr, Local>>>>,
     had_error: RefCell<bool>,
     current_function: RefCell<FunctionType>,
     current_class: RefCell<ClassType>,
 }
 
+// A name declared in a scope. `index` is its slot in the scope's environment,
+// handed out in declaration order.
+struct Local {
+    defined: bool,
+    index: usize,
+}
+
 #[derive(PartialEq)]
 enum FunctionType {
     None,
@@ -30,10 +37,11 @@ enum ClassType {
     None,
     Class,
     SubClass,
+    Trait,
 }
 
 
-impl<'a> StmtVisitor<()> for Resolver<'a>{
+impl StmtVisitor<()> for Resolver {
     fn visit_class_stmt(&self , _: Rc<Stmt>, stmt: &ClassStmt) -> Result<(), Error> {
 
         let enclosing_class = self.current_class.replace(ClassType::Class);
@@ -53,19 +61,36 @@ impl<'a> StmtVisitor<()> for Resolver<'a>{
             self.resolve_expr(superclass);
 
             self.begin_scope();
-            self.scopes
-                .borrow()
-                .last()
-                .unwrap()
-                .borrow_mut()
-                .insert("super".to_string(), true);
+            self.define_name("super");
+        }
 
-            self.begin_scope();
-            self.scopes.borrow().last().unwrap().borrow_mut().insert("super".to_string(), true);
+        for t in stmt.traits.iter() {
+            if let Expr::Variable(v) = t.deref() {
+                if v.name.lexeme == stmt.name.lexeme {
+                    self.error(&v.name, "A class cannot mix in itself.");
+                }
+            }
+            self.resolve_expr(t.clone());
+        }
+
+        for interface in stmt.interfaces.iter() {
+            if let Expr::Variable(v) = interface.deref() {
+                if v.name.lexeme == stmt.name.lexeme {
+                    self.error(&v.name, "A class cannot implement itself.");
+                }
+            }
+            self.resolve_expr(interface.clone());
         }
 
         self.begin_scope();
-        self.scopes.borrow().last().unwrap().borrow_mut().insert("this".to_string(), true);
+        self.define_name("this");
+
+        // Field initializers run like a method without parameters.
+        let enclosing_function = self.current_function.replace(FunctionType::Method);
+        self.begin_scope();
+        self.resolve(&stmt.fields);
+        self.end_scope();
+        self.current_function.replace(enclosing_function);
 
         for method in stmt.methods.deref() {
             if let Stmt::Function(method) = method.deref() {
@@ -79,6 +104,12 @@ impl<'a> StmtVisitor<()> for Resolver<'a>{
                 return Err(Error::runtime_error(&stmt.name, "Class method did not resolve to a function."));
             }
         }
+
+        for accessor in stmt.getters.iter().chain(stmt.setters.iter()) {
+            if let Stmt::Function(accessor) = accessor.deref() {
+                self.resolve_function(accessor, FunctionType::Method);
+            }
+        }
         self.end_scope();
 
         if stmt.superclass.is_some(){
@@ -90,6 +121,33 @@ impl<'a> StmtVisitor<()> for Resolver<'a>{
         Ok(())
     }
 
+    fn visit_trait_stmt(&self, _: Rc<Stmt>, stmt: &TraitStmt) -> Result<(), Error> {
+        let enclosing_class = self.current_class.replace(ClassType::Trait);
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        self.begin_scope();
+        self.define_name("this");
+
+        for method in stmt.methods.deref() {
+            if let Stmt::Function(method) = method.deref() {
+                self.resolve_function(method, FunctionType::Method);
+            }
+        }
+        self.end_scope();
+
+        self.current_class.replace(enclosing_class);
+
+        Ok(())
+    }
+
+    fn visit_interface_stmt(&self, _: Rc<Stmt>, stmt: &InterfaceStmt) -> Result<(), Error> {
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+        Ok(())
+    }
+
     fn visit_return_stmt(&self, _: Rc<Stmt> ,stmt: &ReturnStmt) -> Result<(), Error> {
         if *self.current_function.borrow() == FunctionType::None {
             return Err(Error::runtime_error(&stmt.keyword, "Cannot return from top-level code."));
@@ -99,6 +157,11 @@ impl<'a> StmtVisitor<()> for Resolver<'a>{
             if *self.current_function.borrow() == FunctionType::Initializer {
                 return Err(Error::runtime_error(&stmt.keyword, "Cannot return a value from an initializer."));
             }
+            // Nothing is left to do in this function once the call returns,
+            // so the callee can take over its frame.
+            if let Expr::Call(call) = value.deref() {
+                call.tail.set(true);
+            }
             self.resolve_expr(value);
         }
 
@@ -150,13 +213,16 @@ impl<'a> StmtVisitor<()> for Resolver<'a>{
     }
 }
 
-impl<'a> ExprVisitor<()> for Resolver<'a>{
-    fn visit_super_expr(&self, wrapper: Rc<Expr>, expr: &SuperExpr) -> Result<(), Error> {
+impl ExprVisitor<()> for Resolver {
+    fn visit_super_expr(&self, _: Rc<Expr>, expr: &SuperExpr) -> Result<(), Error> {
         match self.current_class.borrow().deref() {
             ClassType::None => {
                 self.error(&expr.keyword, "Can't use 'super' outside of a class.");
             }
             ClassType::SubClass => {}
+            ClassType::Trait => {
+                self.error(&expr.keyword, "Can't use 'super' in a trait.");
+            }
             _ => {
                 self.error(
                     &expr.keyword,
@@ -165,34 +231,64 @@ impl<'a> ExprVisitor<()> for Resolver<'a>{
             }
         }
 
-        self.resolve_local(wrapper, &expr.keyword);
+        expr.slot.set(self.resolve_local(&expr.keyword));
         Ok(())
     }
         
-    fn visit_this_expr(&self, wrapper: Rc<Expr>, expr: &ThisExpr) -> Result<(), Error> {
+    fn visit_this_expr(&self, _: Rc<Expr>, expr: &ThisExpr) -> Result<(), Error> {
         if *self.current_class.borrow() == ClassType::None {
             self.error(&expr.keyword, "Cannot use 'this' outside of a class.");
             return Ok(());   
         }
-        self.resolve_local(wrapper, &expr.keyword);
+        expr.slot.set(self.resolve_local(&expr.keyword));
         Ok(())
     }
     
     fn visit_set_expr(&self, _: Rc<Expr>, expr: &SetExpr) -> Result<(), Error> {
         self.resolve_expr(expr.value.clone());
         self.resolve_expr(expr.object.clone());
+        self.check_private_access(&expr.object, &expr.name);
         Ok(())
     }
 
     fn visit_get_expr(&self, _: Rc<Expr>, expr: &GetExpr) -> Result<(), Error> {
         self.resolve_expr(expr.object.clone());
+        self.check_private_access(&expr.object, &expr.name);
+        Ok(())
+    }
+
+    fn visit_list_expr(&self, _: Rc<Expr>, expr: &ListExpr) -> Result<(), Error> {
+        for element in expr.elements.iter() {
+            self.resolve_expr(element.clone());
+        }
+        Ok(())
+    }
+
+    fn visit_map_expr(&self, _: Rc<Expr>, expr: &MapExpr) -> Result<(), Error> {
+        for (key, value) in expr.keys.iter().zip(expr.values.iter()) {
+            self.resolve_expr(key.clone());
+            self.resolve_expr(value.clone());
+        }
+        Ok(())
+    }
+
+    fn visit_index_expr(&self, _: Rc<Expr>, expr: &IndexExpr) -> Result<(), Error> {
+        self.resolve_expr(expr.object.clone());
+        self.resolve_expr(expr.index.clone());
+        Ok(())
+    }
+
+    fn visit_setindex_expr(&self, _: Rc<Expr>, expr: &SetIndexExpr) -> Result<(), Error> {
+        self.resolve_expr(expr.value.clone());
+        self.resolve_expr(expr.object.clone());
+        self.resolve_expr(expr.index.clone());
         Ok(())
     }
 
     fn visit_call_expr(&self, _: Rc<Expr>, expr: &CallExpr) -> Result<(), Error> {
         self.resolve_expr(expr.callee.clone());
 
-        for arg in expr.arguments.iter() {
+        for arg in expr.arguments.iter().chain(expr.keyword_values.iter()) {
             self.resolve_expr(arg.clone());
         }
         Ok(())
@@ -202,9 +298,9 @@ impl<'a> ExprVisitor<()> for Resolver<'a>{
         self.resolve_expr(expr.right.clone());
         Ok(())
     }
-    fn visit_assign_expr(&self, wrapper: Rc<Expr>, expr: &AssignExpr) -> Result<(), Error> {
+    fn visit_assign_expr(&self, _: Rc<Expr>, expr: &AssignExpr) -> Result<(), Error> {
         self.resolve_expr(expr.value.clone());
-        self.resolve_local(wrapper, &expr.name);
+        expr.slot.set(self.resolve_local(&expr.name));
         Ok(())
     }
     fn visit_literal_expr(&self, _: Rc<Expr>, _expr: &LiteralExpr) -> Result<(), Error> {
@@ -224,22 +320,21 @@ impl<'a> ExprVisitor<()> for Resolver<'a>{
         self.resolve_expr(expr.right.clone());
         Ok(())
     }
-    fn visit_variable_expr(&self, wrapper: Rc<Expr>, expr: &VariableExpr) -> Result<(), Error> {
-        if !self.scopes.borrow().is_empty() && self.scopes.borrow().last().unwrap().borrow().get(&expr.name.lexeme) == Some(&false){
+    fn visit_variable_expr(&self, _: Rc<Expr>, expr: &VariableExpr) -> Result<(), Error> {
+        if !self.scopes.borrow().is_empty() && self.scopes.borrow().last().unwrap().borrow().get(&expr.name.lexeme).map(|local| local.defined) == Some(false){
             return Err(Error::runtime_error(
                 &expr.name,
                 "Cannot read local variable in its own initializer.",
             ));
         }
-        self.resolve_local(wrapper, &expr.name);
+        expr.slot.set(self.resolve_local(&expr.name));
         Ok(())
     }
 }
 
-impl<'a> Resolver<'a> {
-    pub fn new(interpreter: &'a Interpreter) -> Resolver {
+impl Resolver {
+    pub fn new() -> Resolver {
         Resolver {
-            interpreter,
             scopes: RefCell::new(Vec::new()),
             had_error: RefCell::new(false),
             current_function: RefCell::new(FunctionType::None),
@@ -253,15 +348,21 @@ impl<'a> Resolver<'a> {
     }
 
     pub fn success(&self) -> bool{
-        !self.had_error.borrow().clone()
+        !*self.had_error.borrow()
     }
 
+    // Errors returned by the visitors have already been reported, but still
+    // have to stop the program from running.
     fn resolve_stmt(&self, stmt: Rc<Stmt>){
-        let _ = stmt.accept(stmt.clone(), self);
+        if stmt.accept(stmt.clone(), self).is_err() {
+            self.had_error.replace(true);
+        }
     }
 
     fn resolve_expr(&self, expr: Rc<Expr>){
-        let _ = expr.accept(expr.clone(), self);
+        if expr.accept(expr.clone(), self).is_err() {
+            self.had_error.replace(true);
+        }
     }
 
     fn begin_scope(&self) {
@@ -274,28 +375,40 @@ impl<'a> Resolver<'a> {
 
     fn declare(&self, name: &Token) {
         if let Some(scope) = self.scopes.borrow().last() {
-            if scope.borrow().contains_key(&name.lexeme.clone()) {
+            if scope.borrow().contains_key(&name.lexeme) {
                 self.error(name, "Variable with this name already declared in this scope.");
                 self.had_error.replace(true);
                 return;
             }
-            scope.borrow_mut().insert(name.lexeme.clone(), false);
+            let index = scope.borrow().len();
+            scope.borrow_mut().insert(name.lexeme.clone(), Local { defined: false, index });
         }
     }
 
     fn define(&self, name: &Token) {
         if let Some(scope) = self.scopes.borrow().last() {
-            scope.borrow_mut().insert(name.lexeme.clone(), true);
+            if let Some(local) = scope.borrow_mut().get_mut(&name.lexeme) {
+                local.defined = true;
+            }
         }
     }
 
-    fn resolve_local(&self, expr: Rc<Expr>, name: &Token) {
-        for (i, scope) in self.scopes.borrow().iter().rev().enumerate() {
-            if scope.borrow().contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr.clone(), i);
-                return;
+    // Declares and defines an implicit name such as "this" in the innermost scope.
+    fn define_name(&self, name: &str) {
+        let scopes = self.scopes.borrow();
+        let mut scope = scopes.last().unwrap().borrow_mut();
+        let index = scope.len();
+        scope.insert(intern(name), Local { defined: true, index });
+    }
+
+    // Returns None for globals, which are looked up by name at runtime.
+    fn resolve_local(&self, name: &Token) -> Option<Slot> {
+        for (depth, scope) in self.scopes.borrow().iter().rev().enumerate() {
+            if let Some(local) = scope.borrow().get(&name.lexeme) {
+                return Some(Slot { depth, index: local.index });
             }
         }
+        None
     }
 
     fn resolve_function(&self, function: &FunctionStmt, func_type: FunctionType){
@@ -313,6 +426,20 @@ impl<'a> Resolver<'a> {
         self.current_function.replace(enclosing_function);
     }
 
+    fn check_private_access(&self, object: &Expr, name: &Token) {
+        if !name.is_private() {
+            return;
+        }
+
+        if *self.current_class.borrow() == ClassType::None {
+            self.error(name, "Can't access a private member outside of a class.");
+        } else if *self.current_class.borrow() == ClassType::Trait {
+            self.error(name, "Can't access a private member in a trait.");
+        } else if !matches!(object, Expr::This(_)) {
+            self.error(name, "Private members can only be accessed through 'this'.");
+        }
+    }
+
     fn error(&self, token: &Token, message: &str) {
         self.had_error.replace(true);
         Error::runtime_error(token, message); 
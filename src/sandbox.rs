@@ -0,0 +1,236 @@
+use crate::errors::*;
+use crate::interpreter::RECURSION_LIMIT;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// What a script is allowed to do and use. The default allows everything,
+/// which is right for scripts you wrote yourself. Untrusted scripts should
+/// get limits and lose the capabilities they don't need.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Statements the tree-walker may execute, or instructions the VM may
+    /// run.
+    pub max_steps: Option<u64>,
+    /// How long the script may run, counted from when the interpreter is
+    /// made. Checked between steps, so a native that blocks, such as
+    /// `readLine` or `shell`, can overrun it.
+    pub timeout: Option<Duration>,
+    /// Bytes the interpreter's thread may allocate beyond what it had
+    /// allocated when the interpreter was made.
+    pub max_heap: Option<usize>,
+    /// Bytes of program output.
+    pub max_output: Option<usize>,
+    /// Groups of natives the script may call. The rest are not defined.
+    pub capabilities: HashSet<Capability>,
+    pub recursion_limit: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_steps: None,
+            timeout: None,
+            max_heap: None,
+            max_output: None,
+            capabilities: Capability::all(),
+            recursion_limit: RECURSION_LIMIT,
+        }
+    }
+}
+
+impl Config {
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Natives that reach outside the interpreter, grouped by what they touch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// Reading and writing files and directories.
+    Files,
+    /// Environment variables, the working directory, running commands and
+    /// exiting.
+    Processes,
+    /// Talking to other machines. No natives need this yet, but sandboxes
+    /// that deny it will stay closed when some do.
+    Network,
+}
+
+impl Capability {
+    pub fn all() -> HashSet<Capability> {
+        HashSet::from([Capability::Files, Capability::Processes, Capability::Network])
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        match name {
+            "files" => Some(Capability::Files),
+            "processes" => Some(Capability::Processes),
+            "network" => Some(Capability::Network),
+            _ => None,
+        }
+    }
+}
+
+/// A limit a script ran into. Running into one stops the script with a
+/// `LimitError`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    Time,
+    Heap,
+    Output,
+}
+
+// Steps between looks at the clock, which is slow next to a step.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Keeps count of what a script has used against its config's limits.
+pub struct Meter {
+    steps: Cell<u64>,
+    // The step at which to next look at the limits. Until then a step
+    // only has to be counted.
+    checkpoint: Cell<u64>,
+    max_steps: u64,
+    deadline: Option<Instant>,
+    heap_base: isize,
+    max_heap: Option<usize>,
+    output: Cell<usize>,
+    max_output: Option<usize>,
+}
+
+impl Meter {
+    pub fn new(config: &Config) -> Meter {
+        let meter = Meter {
+            steps: Cell::new(0),
+            checkpoint: Cell::new(0),
+            max_steps: config.max_steps.unwrap_or(u64::MAX),
+            deadline: config.timeout.map(|timeout| Instant::now() + timeout),
+            heap_base: allocated(),
+            max_heap: config.max_heap,
+            output: Cell::new(0),
+            max_output: config.max_output,
+        };
+        meter.plan(0);
+        meter
+    }
+
+    /// Counts one step, failing if that goes past a limit.
+    #[inline]
+    pub fn step(&self) -> Result<(), Error> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if steps < self.checkpoint.get() {
+            return Ok(());
+        }
+        self.check(steps)
+    }
+
+    fn check(&self, steps: u64) -> Result<(), Error> {
+        if steps > self.max_steps {
+            return Err(Error::limit_error(Limit::Steps, &format!(
+                "Step budget of {} exceeded.",
+                self.max_steps
+            )));
+        }
+        if let Some(max_heap) = self.max_heap {
+            if allocated() - self.heap_base > max_heap as isize {
+                return Err(Error::limit_error(Limit::Heap, &format!(
+                    "Heap limit of {max_heap} bytes exceeded."
+                )));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() > deadline {
+                return Err(Error::limit_error(Limit::Time, "Timeout exceeded."));
+            }
+        }
+        self.plan(steps);
+        Ok(())
+    }
+
+    // Sets the next checkpoint: the first step over budget, or sooner if
+    // the clock or the heap needs watching. A single step can allocate a
+    // lot, so the heap is looked at after every one.
+    fn plan(&self, steps: u64) {
+        let mut checkpoint = self.max_steps.saturating_add(1);
+        if self.deadline.is_some() {
+            checkpoint = checkpoint.min(steps + CLOCK_INTERVAL);
+        }
+        if self.max_heap.is_some() {
+            checkpoint = steps + 1;
+        }
+        self.checkpoint.set(checkpoint);
+    }
+
+    /// Counts `bytes` of output before they are written, failing if they
+    /// would go past the output limit.
+    pub fn output(&self, bytes: usize) -> Result<(), Error> {
+        let output = self.output.get() + bytes;
+        if let Some(max_output) = self.max_output {
+            if output > max_output {
+                return Err(Error::limit_error(Limit::Output, &format!(
+                    "Output limit of {max_output} bytes exceeded."
+                )));
+            }
+        }
+        self.output.set(output);
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Bytes allocated and not yet freed by this thread. Memory freed by
+    // another thread than the one that allocated it throws this off, but
+    // the interpreter keeps its values to one thread.
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+}
+
+fn allocated() -> isize {
+    ALLOCATED.with(Cell::get)
+}
+
+fn count(bytes: isize) {
+    // Fails once the thread is shutting down, when nothing is being metered.
+    let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + bytes));
+}
+
+/// The system allocator, keeping count of each thread's allocations so
+/// heap limits can be enforced.
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            count(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        count(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            count(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
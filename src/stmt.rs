@@ -9,8 +9,10 @@ pub enum Stmt {
     Expression(Rc<ExpressionStmt>),
     Function(Rc<FunctionStmt>),
     If(Rc<IfStmt>),
+    Interface(Rc<InterfaceStmt>),
     Print(Rc<PrintStmt>),
     Return(Rc<ReturnStmt>),
+    Trait(Rc<TraitStmt>),
     Var(Rc<VarStmt>),
     While(Rc<WhileStmt>),
 }
@@ -23,8 +25,10 @@ impl PartialEq for Stmt {
             (Stmt::Expression(a), Stmt::Expression(b)) => Rc::ptr_eq(a, b),
             (Stmt::Function(a), Stmt::Function(b)) => Rc::ptr_eq(a, b),
             (Stmt::If(a), Stmt::If(b)) => Rc::ptr_eq(a, b),
+            (Stmt::Interface(a), Stmt::Interface(b)) => Rc::ptr_eq(a, b),
             (Stmt::Print(a), Stmt::Print(b)) => Rc::ptr_eq(a, b),
             (Stmt::Return(a), Stmt::Return(b)) => Rc::ptr_eq(a, b),
+            (Stmt::Trait(a), Stmt::Trait(b)) => Rc::ptr_eq(a, b),
             (Stmt::Var(a), Stmt::Var(b)) => Rc::ptr_eq(a, b),
             (Stmt::While(a), Stmt::While(b)) => Rc::ptr_eq(a, b),
             _ => false,
@@ -44,8 +48,10 @@ impl Hash for Stmt {
         Stmt::Expression(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::Function(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::If(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Stmt::Interface(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::Print(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::Return(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
+        Stmt::Trait(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::Var(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         Stmt::While(a) => { hasher.write_usize(Rc::as_ptr(a) as usize); }
         }
@@ -60,8 +66,10 @@ impl Stmt {
             Stmt::Expression(v) => stmt_visitor.visit_expression_stmt(wrapper, v),
             Stmt::Function(v) => stmt_visitor.visit_function_stmt(wrapper, v),
             Stmt::If(v) => stmt_visitor.visit_if_stmt(wrapper, v),
+            Stmt::Interface(v) => stmt_visitor.visit_interface_stmt(wrapper, v),
             Stmt::Print(v) => stmt_visitor.visit_print_stmt(wrapper, v),
             Stmt::Return(v) => stmt_visitor.visit_return_stmt(wrapper, v),
+            Stmt::Trait(v) => stmt_visitor.visit_trait_stmt(wrapper, v),
             Stmt::Var(v) => stmt_visitor.visit_var_stmt(wrapper, v),
             Stmt::While(v) => stmt_visitor.visit_while_stmt(wrapper, v),
         }
@@ -75,7 +83,13 @@ pub struct BlockStmt {
 pub struct ClassStmt {
     pub name: Token,
     pub superclass: Option<Rc<Expr>>,
+    pub traits: Rc<Vec<Rc<Expr>>>,
+    pub interfaces: Rc<Vec<Rc<Expr>>>,
+    pub fields: Rc<Vec<Rc<Stmt>>>,
     pub methods: Rc<Vec<Rc<Stmt>>>,
+    pub getters: Rc<Vec<Rc<Stmt>>>,
+    pub setters: Rc<Vec<Rc<Stmt>>>,
+    pub abstract_methods: Rc<Vec<Rc<Stmt>>>,
 }
 
 pub struct ExpressionStmt {
@@ -94,6 +108,11 @@ pub struct IfStmt {
     pub else_branch: Option<Rc<Stmt>>,
 }
 
+pub struct InterfaceStmt {
+    pub name: Token,
+    pub methods: Rc<Vec<Rc<Stmt>>>,
+}
+
 pub struct PrintStmt {
     pub expression: Rc<Expr>,
 }
@@ -103,6 +122,12 @@ pub struct ReturnStmt {
     pub value: Option<Rc<Expr>>,
 }
 
+pub struct TraitStmt {
+    pub name: Token,
+    pub methods: Rc<Vec<Rc<Stmt>>>,
+    pub required: Rc<Vec<Rc<Stmt>>>,
+}
+
 pub struct VarStmt {
     pub name: Token,
     pub initializer: Option<Rc<Expr>>,
@@ -119,8 +144,10 @@ pub trait StmtVisitor<T> {
     fn visit_expression_stmt(&self, wrapper: Rc<Stmt>, stmt: &ExpressionStmt) -> Result<T, Error>;
     fn visit_function_stmt(&self, wrapper: Rc<Stmt>, stmt: &FunctionStmt) -> Result<T, Error>;
     fn visit_if_stmt(&self, wrapper: Rc<Stmt>, stmt: &IfStmt) -> Result<T, Error>;
+    fn visit_interface_stmt(&self, wrapper: Rc<Stmt>, stmt: &InterfaceStmt) -> Result<T, Error>;
     fn visit_print_stmt(&self, wrapper: Rc<Stmt>, stmt: &PrintStmt) -> Result<T, Error>;
     fn visit_return_stmt(&self, wrapper: Rc<Stmt>, stmt: &ReturnStmt) -> Result<T, Error>;
+    fn visit_trait_stmt(&self, wrapper: Rc<Stmt>, stmt: &TraitStmt) -> Result<T, Error>;
     fn visit_var_stmt(&self, wrapper: Rc<Stmt>, stmt: &VarStmt) -> Result<T, Error>;
     fn visit_while_stmt(&self, wrapper: Rc<Stmt>, stmt: &WhileStmt) -> Result<T, Error>;
 }
@@ -0,0 +1,156 @@
+use crate::errors::*;
+use crate::interpreter::*;
+use crate::native_functions::*;
+use crate::tokens::*;
+
+// All indexes and lengths count Unicode scalar values, not bytes.
+
+pub fn natives() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("substr", 3, native_substr),
+        NativeFunction::new("split", 2, native_split),
+        NativeFunction::new("join", 2, native_join),
+        NativeFunction::new("trim", 1, native_trim),
+        NativeFunction::new("upper", 1, native_upper),
+        NativeFunction::new("lower", 1, native_lower),
+        NativeFunction::new("replace", 3, native_replace),
+        NativeFunction::new("find", 2, native_find),
+        NativeFunction::new("startsWith", 2, native_starts_with),
+        NativeFunction::new("endsWith", 2, native_ends_with),
+        NativeFunction::new("repeat", 2, native_repeat),
+        NativeFunction::new("chars", 1, native_chars),
+        NativeFunction::new("ord", 1, native_ord),
+        NativeFunction::new("chr", 1, native_chr),
+    ]
+}
+
+fn string_list(parts: Vec<String>) -> Object {
+    Object::list(parts.into_iter().map(|part| Object::Str(part.into())).collect())
+}
+
+fn native_substr(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "substr")?;
+    let start = count_arg(&args[1], "substr")?;
+    let length = count_arg(&args[2], "substr")?;
+
+    let len = s.chars().count();
+    if start.checked_add(length).is_none_or(|end| end > len) {
+        return Err(Error::call_error(&format!(
+            "substr() range of length {} from {} out of bounds for string of length {}.",
+            length,
+            start,
+            len
+        )));
+    }
+    Ok(Object::Str(s.chars().skip(start).take(length).collect::<String>().into()))
+}
+
+fn native_split(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "split")?;
+    let separator = string_arg(&args[1], "split")?;
+
+    if separator.is_empty() {
+        return Ok(string_list(s.chars().map(String::from).collect()));
+    }
+    Ok(string_list(s.split(separator).map(String::from).collect()))
+}
+
+fn native_join(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let separator = string_arg(&args[1], "join")?;
+    match &args[0] {
+        Object::List(list) => {
+            let parts: Vec<String> = list.borrow().iter().map(|o| o.to_string()).collect();
+            Ok(Object::Str(parts.join(separator).into()))
+        }
+        _ => Err(Error::call_error("join() expects a list.")),
+    }
+}
+
+fn native_trim(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Str(string_arg(&args[0], "trim")?.trim().into()))
+}
+
+fn native_upper(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Str(string_arg(&args[0], "upper")?.to_uppercase().into()))
+}
+
+fn native_lower(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    Ok(Object::Str(string_arg(&args[0], "lower")?.to_lowercase().into()))
+}
+
+fn native_replace(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "replace")?;
+    let from = string_arg(&args[1], "replace")?;
+    let to = string_arg(&args[2], "replace")?;
+
+    if from.is_empty() {
+        return Err(Error::call_error("replace() cannot replace an empty string."));
+    }
+    Ok(Object::Str(s.replace(from, to).into()))
+}
+
+fn native_find(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "find")?;
+    let needle = string_arg(&args[1], "find")?;
+
+    match s.find(needle) {
+        Some(byte) => Ok(Object::Num(s[..byte].chars().count() as f64)),
+        None => Ok(Object::Num(-1.0)),
+    }
+}
+
+fn native_starts_with(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "startsWith")?;
+    let prefix = string_arg(&args[1], "startsWith")?;
+    Ok(Object::Bool(s.starts_with(prefix)))
+}
+
+fn native_ends_with(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "endsWith")?;
+    let suffix = string_arg(&args[1], "endsWith")?;
+    Ok(Object::Bool(s.ends_with(suffix)))
+}
+
+fn native_repeat(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "repeat")?;
+    let count = count_arg(&args[1], "repeat")?;
+    // Reserved up front so a result too big to allocate is an error rather
+    // than an abort.
+    let mut repeated = String::new();
+    match s.len().checked_mul(count) {
+        Some(bytes) if repeated.try_reserve_exact(bytes).is_ok() => {
+            if !s.is_empty() {
+                for _ in 0..count {
+                    repeated.push_str(s);
+                }
+            }
+            Ok(Object::Str(repeated.into()))
+        }
+        _ => Err(Error::call_error("repeat() result is too long.")),
+    }
+}
+
+fn native_chars(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "chars")?;
+    Ok(string_list(s.chars().map(String::from).collect()))
+}
+
+fn native_ord(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let s = string_arg(&args[0], "ord")?;
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Object::Num(c as u32 as f64)),
+        _ => Err(Error::call_error("ord() expects a string of exactly one character.")),
+    }
+}
+
+fn native_chr(_terp: &Interpreter, args: &[Object]) -> Result<Object, Error> {
+    let code = count_arg(&args[0], "chr")?;
+    match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(Object::Str(c.to_string().into())),
+        None => Err(Error::call_error(&format!(
+            "chr() got invalid code point {}.",
+            code
+        ))),
+    }
+}
@@ -3,11 +3,22 @@ use crate::errors::*;
 use crate::interpreter::Interpreter;
 use crate::callable::*;
 use std::rc::Rc;
+use std::cell::RefCell;
 use crate::instance::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::functions::*;
 use crate::native_functions::*;
+use crate::traits::*;
+use crate::map::*;
+use crate::module::*;
+use crate::regex::Regex;
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
 
+/// How many lists and maps deep `Display` goes before printing `[...]`.
+const MAX_DEPTH: usize = 512;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Default, PartialEq, Clone)]
 pub enum TokenKind {
     #[default]
@@ -18,6 +29,7 @@ pub enum TokenKind {
     Number,
     String,
     Identifier,
+    PrivateIdentifier, // #name
 
     //Operators
     Plus,             // +
@@ -38,14 +50,13 @@ pub enum TokenKind {
     RightParen, // )
     LeftBrace,  // {
     RightBrace, // }
-    RightSquare, // [
-    LeftSquare,  // ]
+    LeftSquare,  // [
+    RightSquare, // ]
     Comma,      // ,
+    Colon,      // :
     Dot,        // .
     Semicolon,  // ;
 
-    Comment, // #
-
     //Keywords
     And, 
     Class,
@@ -54,46 +65,122 @@ pub enum TokenKind {
     Fn,
     For,
     If,
+    Implements,
+    Interface,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Trait,
     True,
     Var,
     While,
+    With,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Object {
     Num(f64),
-    Str(String),
+    Str(RcStr),
     Bool(bool),
+    List(Rc<RefCell<Vec<Object>>>),
+    Map(Rc<RefCell<MapStruct>>),
     Function(Rc<Function>),
     Class(Rc<ClassStruct>),
     Instance(Rc<InstanceStruct>),
+    Trait(Rc<TraitStruct>),
+    Interface(Rc<InterfaceStruct>),
     Native(Rc<Native>),
+    Module(Rc<ModuleStruct>),
+    Regex(Rc<Regex>),
     Nil,
     ArithmeticError,
 }
 
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &mut HashSet::new())
+    }
+}
+
+impl Object {
+    // `printing` holds the lists and maps being written further up, so one
+    // that contains itself prints as `[...]` or `{...}` there instead of
+    // recursing forever. Nesting deeper than `MAX_DEPTH` is cut off the same
+    // way, before it can overflow the stack.
+    fn write(&self, f: &mut fmt::Formatter, printing: &mut HashSet<usize>) -> fmt::Result {
         match self {
             Object::Num(x) => write!(f, "{x}"),
             Object::Str(x) => write!(f, "{x}"),
             Object::Nil => write!(f, "nil"),
             Object::Bool(x) => write!(f, "{x}"),
+            Object::List(list) => {
+                let id = gc::address(list);
+                if printing.len() >= MAX_DEPTH || !printing.insert(id) {
+                    return write!(f, "[...]");
+                }
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match element {
+                        Object::Str(x) => write!(f, "{x:?}")?,
+                        _ => element.write(f, printing)?,
+                    }
+                }
+                printing.remove(&id);
+                write!(f, "]")
+            }
+            Object::Map(map) => {
+                let id = gc::address(map);
+                if printing.len() >= MAX_DEPTH || !printing.insert(id) {
+                    return write!(f, "{{...}}");
+                }
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().entries().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match value {
+                        Object::Str(x) => write!(f, "{key:?}: {x:?}")?,
+                        _ => {
+                            write!(f, "{key:?}: ")?;
+                            value.write(f, printing)?;
+                        }
+                    }
+                }
+                printing.remove(&id);
+                write!(f, "}}")
+            }
             Object::ArithmeticError => write!(f, "Arithmetic Error"),
             Object::Function(_) => write!(f, "<func>"),
             Object::Native(_) => write!(f, "<native>"),
             Object::Class(c) => write!(f, "<class {}>", c.name),
             Object::Instance(i) => write!(f, "<instance {}>", i.class.name),
+            Object::Trait(t) => write!(f, "<trait {}>", t.name),
+            Object::Interface(i) => write!(f, "<interface {}>", i.name),
+            Object::Module(m) => write!(f, "<module {}>", m.name),
+            Object::Regex(r) => write!(f, "{r}"),
         }
     }
-}
 
+    /// A new list, known to the collector.
+    pub fn list(elements: Vec<Object>) -> Object {
+        let list = Rc::new(RefCell::new(elements));
+        gc::track(&list);
+        Object::List(list)
+    }
+
+    /// A new map, known to the collector.
+    pub fn map(map: MapStruct) -> Object {
+        let map = Rc::new(RefCell::new(map));
+        gc::track(&map);
+        Object::Map(map)
+    }
+}
 
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -101,25 +188,62 @@ impl fmt::Display for Token {
     }
 }
 
+/// Everything declared in a class body, as built by `visit_class_stmt`.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ClassMembers {
+    pub methods: HashMap<RcStr, Object>,
+    pub getters: HashMap<RcStr, Object>,
+    pub setters: HashMap<RcStr, Object>,
+    /// Abstract method names with their arity, in declaration order.
+    pub abstract_methods: Vec<(String, usize)>,
+    /// Declared field names, in declaration order.
+    pub fields: Vec<String>,
+    /// Assigns the declared fields on a new instance.
+    pub field_initializer: Option<Rc<Function>>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct ClassStruct {
+    pub id: usize,
     pub name: String,
     superclass: Option<Rc<ClassStruct>>,
-    methods: HashMap<String, Object>,
+    pub traits: Vec<Rc<TraitStruct>>,
+    pub interfaces: Vec<Rc<InterfaceStruct>>,
+    members: ClassMembers,
 }
 
 impl ClassStruct {
-    pub fn new(name: String, superclass: Option<Rc<ClassStruct>>, methods: HashMap<String, Object>) -> Self {
+    pub fn new(
+        id: usize,
+        name: String,
+        superclass: Option<Rc<ClassStruct>>,
+        traits: Vec<Rc<TraitStruct>>,
+        interfaces: Vec<Rc<InterfaceStruct>>,
+        members: ClassMembers,
+    ) -> Self {
         ClassStruct {
+            id,
             name,
             superclass,
-            methods,
+            traits,
+            interfaces,
+            members,
         }
     }
 
     pub fn instantiate(&self, interpreter: &Interpreter, arguments: Vec<Object>, cls: Rc<ClassStruct>) -> Result<Object, Error> {
-        let instance = Object::Instance(Rc::new(InstanceStruct::new(cls)));
-        if let Some(Object::Function(initializer)) = self.find_method("init".to_string()){
+        let missing = self.missing_abstract_methods();
+        if !missing.is_empty() {
+            return Err(Error::call_error(&format!(
+                "Cannot instantiate abstract class '{}'. Missing implementations: {}.",
+                self.name,
+                missing.join(", ")
+            )));
+        }
+
+        let instance = Object::Instance(InstanceStruct::new(cls));
+        self.initialize_fields(interpreter, &instance)?;
+        if let Some(Object::Function(initializer)) = self.find_method("init"){
             if let Object::Function(initializer) = initializer.bind(&instance) {
                 initializer.call(interpreter, &arguments, None)?;
             }
@@ -127,8 +251,70 @@ impl ClassStruct {
         Ok(instance)
     }
 
-    pub fn find_method(&self, name: String) -> Option<Object> {
-        if let Some(method) = self.methods.get(&name){
+    // Inherited field declarations run first, so a subclass can override
+    // the value a base class gives a field.
+    fn initialize_fields(&self, interpreter: &Interpreter, instance: &Object) -> Result<(), Error> {
+        if let Some(superclass) = &self.superclass {
+            superclass.initialize_fields(interpreter, instance)?;
+        }
+        if let Some(initializer) = &self.members.field_initializer {
+            if let Object::Function(initializer) = initializer.bind(instance) {
+                initializer.call(interpreter, &[], None)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn superclass(&self) -> Option<Rc<ClassStruct>> {
+        self.superclass.clone()
+    }
+
+    /// Whether `other` is this class or one of its ancestors.
+    pub fn is_subclass_of(&self, other: &ClassStruct) -> bool {
+        self.find_class(other.id).is_some()
+    }
+
+    pub fn has_trait(&self, t: &Rc<TraitStruct>) -> bool {
+        self.traits.iter().any(|own| Rc::ptr_eq(own, t))
+            || self.superclass.as_ref().is_some_and(|s| s.has_trait(t))
+    }
+
+    pub fn implements(&self, interface: &Rc<InterfaceStruct>) -> bool {
+        self.interfaces.iter().any(|own| Rc::ptr_eq(own, interface))
+            || self.superclass.as_ref().is_some_and(|s| s.implements(interface))
+    }
+
+    /// Names of the public methods this class has or inherits, sorted.
+    pub fn method_names(&self) -> Vec<String> {
+        let mut names = match &self.superclass {
+            Some(superclass) => superclass.method_names(),
+            None => Vec::new(),
+        };
+        for name in self.members.methods.keys() {
+            if !name.starts_with('#') && !names.iter().any(|own| own == &**name) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        names
+    }
+
+    /// The declared fields of this class and its superclasses.
+    pub fn field_names(&self) -> Vec<String> {
+        let mut names = match &self.superclass {
+            Some(superclass) => superclass.field_names(),
+            None => Vec::new(),
+        };
+        for name in self.members.fields.iter() {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Object> {
+        if let Some(method) = self.members.methods.get(name){
             Some(method.clone())
         }else if let Some(superclass) = &self.superclass {
             superclass.find_method(name)
@@ -136,15 +322,108 @@ impl ClassStruct {
             None
         }
     }
+
+    /// Returns the class in this hierarchy with the given id. Private members
+    /// are looked up on that class only, since they are never inherited.
+    pub fn find_class(&self, id: usize) -> Option<&ClassStruct> {
+        if self.id == id {
+            Some(self)
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_class(id)
+        } else {
+            None
+        }
+    }
+
+    pub fn own_method(&self, name: &str) -> Option<Object> {
+        self.members.methods.get(name).cloned()
+    }
+
+    pub fn own_getter(&self, name: &str) -> Option<Object> {
+        self.members.getters.get(name).cloned()
+    }
+
+    pub fn own_setter(&self, name: &str) -> Option<Object> {
+        self.members.setters.get(name).cloned()
+    }
+
+    pub fn find_getter(&self, name: &str) -> Option<Object> {
+        if let Some(getter) = self.members.getters.get(name) {
+            Some(getter.clone())
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_getter(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn find_setter(&self, name: &str) -> Option<Object> {
+        if let Some(setter) = self.members.setters.get(name) {
+            Some(setter.clone())
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_setter(name)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the arity of an abstract method that no class between here
+    /// and its declaration has implemented.
+    pub fn find_abstract(&self, name: &str) -> Option<usize> {
+        if self.members.methods.contains_key(name) {
+            None
+        } else if let Some((_, arity)) = self.members.abstract_methods.iter().find(|(n, _)| n == name) {
+            Some(*arity)
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_abstract(name)
+        } else {
+            None
+        }
+    }
+
+    pub fn missing_abstract_methods(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut missing = Vec::new();
+        let mut class = Some(self);
+        while let Some(c) = class {
+            for name in c.members.methods.keys() {
+                seen.insert(name.to_string());
+            }
+            for (name, _) in c.members.abstract_methods.iter() {
+                if seen.insert(name.clone()) {
+                    missing.push(name.clone());
+                }
+            }
+            class = c.superclass.as_deref();
+        }
+        missing
+    }
+}
+
+impl Trace for ClassStruct {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        if let Some(superclass) = &self.superclass {
+            refs.push(gc::address(superclass));
+        }
+        refs.extend(self.traits.iter().map(gc::address));
+        let members = &self.members;
+        for member in members.methods.values().chain(members.getters.values()).chain(members.setters.values()) {
+            gc::object_refs(member, refs);
+        }
+        if let Some(initializer) = &members.field_initializer {
+            refs.push(gc::address(initializer));
+        }
+        true
+    }
 }
 
 impl CallableTrait for ClassStruct {
-    fn call(&self, interpreter: &Interpreter, arguments: &Vec<Object>, class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
-        self.instantiate(interpreter, arguments.clone(), class.unwrap())
+    fn call(&self, interpreter: &Interpreter, arguments: &[Object], class: Option<Rc<ClassStruct>>) -> Result<Object, Error> {
+        self.instantiate(interpreter, arguments.to_vec(), class.unwrap())
     }
 
     fn arity(&self) -> usize {
-        if let Some(Object::Function(initializer)) = self.find_method("init".to_string()){
+        if let Some(Object::Function(initializer)) = self.find_method("init"){
             return initializer.arity();
         }
         0
@@ -158,7 +437,7 @@ impl CallableTrait for ClassStruct {
 #[derive(Debug, PartialEq, Clone)]
 pub struct Token {
     pub kind: TokenKind,
-    pub lexeme: String,
+    pub lexeme: RcStr,
     pub literal: Option<Object>,
     pub line: usize,
 }
@@ -167,7 +446,7 @@ impl Default for Token {
     fn default() -> Self {
         Token {
             kind: TokenKind::Illegal,
-            lexeme: "".to_string(),
+            lexeme: RcStr::default(),
             literal: None,
             line: 0,
         }
@@ -175,7 +454,7 @@ impl Default for Token {
 }
 
 impl Token {
-    pub fn new(kind: TokenKind, lexeme: String, literal: Option<Object>, line: usize) -> Self {
+    pub fn new(kind: TokenKind, lexeme: RcStr, literal: Option<Object>, line: usize) -> Self {
         Token {
             kind,
             lexeme,
@@ -183,4 +462,44 @@ impl Token {
             line,
         }
     }
+
+    pub fn is_private(&self) -> bool {
+        self.kind == TokenKind::PrivateIdentifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize, wrap: fn(Object) -> Object) -> Object {
+        (0..depth).fold(Object::Nil, |inner, _| wrap(inner))
+    }
+
+    #[test]
+    fn cuts_off_deeply_nested_lists() {
+        let list = nested(1000, |inner| Object::list(vec![inner]));
+        let text = list.to_string();
+        let cut = format!("{}[...]{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert_eq!(text, cut);
+    }
+
+    #[test]
+    fn cuts_off_deeply_nested_maps() {
+        let map = nested(1000, |inner| {
+            let mut map = MapStruct::new();
+            map.insert("m".into(), inner);
+            Object::map(map)
+        });
+        let text = map.to_string();
+        let cut = format!("{}{{...}}{}", "{\"m\": ".repeat(MAX_DEPTH), "}".repeat(MAX_DEPTH));
+        assert_eq!(text, cut);
+    }
+
+    #[test]
+    fn prints_nesting_up_to_the_cap() {
+        let list = nested(MAX_DEPTH, |inner| Object::list(vec![inner]));
+        let text = list.to_string();
+        assert_eq!(text, format!("{}nil{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH)));
+    }
 }
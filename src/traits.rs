@@ -0,0 +1,45 @@
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
+use crate::tokens::*;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TraitStruct {
+    pub name: String,
+    pub methods: HashMap<RcStr, Object>,
+    /// Methods the implementing class must provide, with their arity.
+    pub required: Vec<(String, usize)>,
+}
+
+impl TraitStruct {
+    pub fn new(name: String, methods: HashMap<RcStr, Object>, required: Vec<(String, usize)>) -> Rc<Self> {
+        let t = Rc::new(TraitStruct {
+            name,
+            methods,
+            required,
+        });
+        gc::track(&t);
+        t
+    }
+}
+
+impl Trace for TraitStruct {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        self.methods.values().for_each(|method| gc::object_refs(method, refs));
+        true
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct InterfaceStruct {
+    pub name: String,
+    /// Methods a conforming class must provide, with their arity.
+    pub methods: Vec<(String, usize)>,
+}
+
+impl InterfaceStruct {
+    pub fn new(name: String, methods: Vec<(String, usize)>) -> Self {
+        InterfaceStruct { name, methods }
+    }
+}
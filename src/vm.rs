@@ -0,0 +1,668 @@
+use crate::errors::*;
+use crate::functions::Function;
+use crate::gc::{self, Trace};
+use crate::interner::RcStr;
+use crate::interpreter::Interpreter;
+use crate::map::*;
+use crate::tokens::*;
+use crate::traits::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// One VM instruction. Operands index the tables of the chunk the
+/// instruction belongs to. A token operand names a variable or property,
+/// or marks where an error is reported.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    DefineGlobal(u32),
+    GetGlobal(u32),
+    SetGlobal(u32),
+    GetProperty(u32),
+    SetProperty(u32),
+    /// Pops the superclass and `this`, and pushes the named member.
+    GetSuper(u32),
+    GetIndex(u32),
+    SetIndex(u32),
+    Add(u32),
+    Subtract(u32),
+    Multiply(u32),
+    Divide(u32),
+    Equal(u32),
+    NotEqual(u32),
+    Greater(u32),
+    GreaterEqual(u32),
+    Less(u32),
+    LessEqual(u32),
+    Not,
+    Negate,
+    Print,
+    /// Jumps take the index of the instruction to continue at.
+    Jump(u32),
+    /// Jumps if the value on top of the stack is falsy, leaving it there.
+    JumpIfFalse(u32),
+    /// Jumps if the value on top of the stack is truthy, leaving it there.
+    JumpIfTrue(u32),
+    /// Pops a condition and jumps if it is falsy.
+    PopJumpIfFalse(u32),
+    /// Calls with a number of arguments, reporting errors at the token.
+    Call(u8, u32),
+    /// Calls with a number of positional arguments and an entry of the
+    /// chunk's keyword table.
+    CallKeywords(u8, u32),
+    /// A call whose value the caller returns. A compiled callee takes over
+    /// the caller's frame; anything else is called as usual.
+    TailCall(u8, u32),
+    Closure(u32),
+    CloseUpvalue,
+    Return,
+    List(u32),
+    /// Builds a map from a number of key and value pairs.
+    Map(u16, u32),
+    /// Checks the value on top of the stack is a class, trait or interface.
+    Expect(Kind, u32),
+    Class(u32),
+    Trait(u32),
+    Interface(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Class,
+    Trait,
+    Interface,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub constants: Vec<Object>,
+    pub tokens: Vec<Token>,
+    pub prototypes: Vec<Rc<Prototype>>,
+    pub layouts: Vec<Layout>,
+    /// Keyword names and closing paren of each call with keyword arguments.
+    pub keyword_calls: Vec<(Vec<Token>, Token)>,
+}
+
+/// What `Op::Class`, `Op::Trait` and `Op::Interface` build. A class finds
+/// its superclass, traits, interfaces, field initializer, methods, getters
+/// and setters on the stack, in that order. A trait finds its methods.
+#[derive(Debug, Default)]
+pub struct Layout {
+    pub name: Token,
+    pub superclass: bool,
+    pub traits: usize,
+    pub interfaces: usize,
+    pub fields: Vec<String>,
+    pub field_initializer: bool,
+    pub methods: Vec<RcStr>,
+    pub getters: Vec<RcStr>,
+    pub setters: Vec<RcStr>,
+    /// Abstract methods of a class, required methods of a trait, or the
+    /// methods of an interface, with their arity.
+    pub signatures: Vec<(String, usize)>,
+}
+
+/// A compiled function, before it captures any variables.
+#[derive(Debug)]
+pub struct Prototype {
+    pub name: Token,
+    pub params: Rc<Vec<Token>>,
+    pub is_initializer: bool,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+/// Where a closure finds a captured variable: in a local slot of the
+/// function creating it, or among that function's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local(u32),
+    Upvalue(u32),
+}
+
+/// A captured variable. It stays on the stack while the function that
+/// declared it is running, and moves into the upvalue when it returns.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Object),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub prototype: Rc<Prototype>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Trace for Closure {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        refs.extend(self.upvalues.iter().map(gc::address));
+        true
+    }
+
+    fn is_value(&self) -> bool {
+        false
+    }
+}
+
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, refs: &mut Vec<usize>) -> bool {
+        let Ok(upvalue) = self.try_borrow_mut() else {
+            return false;
+        };
+        if let Upvalue::Closed(value) = &*upvalue {
+            gc::object_refs(value, refs);
+        }
+        true
+    }
+
+    fn clear(&self) {
+        self.replace(Upvalue::Closed(Object::Nil));
+    }
+
+    fn is_value(&self) -> bool {
+        false
+    }
+}
+
+/// The value stack, shared by every call into compiled code so that open
+/// upvalues stay valid when natives call back into the VM.
+#[derive(Default)]
+pub struct Vm {
+    stack: RefCell<Vec<Object>>,
+    open_upvalues: RefCell<Vec<(usize, Rc<RefCell<Upvalue>>)>>,
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot 0, which holds `this` in methods.
+    base: usize,
+    /// The running class to restore when the frame returns.
+    class: Option<usize>,
+}
+
+/// Runs a compiled script, stopping at the first error.
+pub fn interpret(interpreter: &Interpreter, script: Rc<Prototype>) -> Result<(), Error> {
+    let closure = Rc::new(Closure {
+        prototype: script,
+        upvalues: Vec::new(),
+    });
+    let result = call(interpreter, &closure, None, None, &[]);
+    let _ = interpreter.flush_output();
+    // A call error with no call site left to report it at.
+    if let Err(error @ Error::CallError { .. }) = &result {
+        error.report("");
+    }
+    result.map(|_| ())
+}
+
+/// Calls a compiled function whose arguments have already been checked.
+pub fn call(
+    interpreter: &Interpreter,
+    closure: &Rc<Closure>,
+    this: Option<&Object>,
+    owner: Option<usize>,
+    arguments: &[Object],
+) -> Result<Object, Error> {
+    let mut machine = Machine {
+        interpreter,
+        stack: interpreter.vm().stack.take(),
+        frames: Vec::new(),
+    };
+    let base = machine.stack.len();
+    machine.stack.push(this.cloned().unwrap_or(Object::Nil));
+    machine.stack.extend_from_slice(arguments);
+
+    let class = interpreter.set_current_class(owner);
+    let depth = interpreter.call_depth();
+    let frame = Frame {
+        closure: Rc::clone(closure),
+        ip: 0,
+        base,
+        class,
+    };
+    let result = machine.run(frame);
+    if result.is_err() {
+        machine.close_upvalues(base);
+        machine.stack.truncate(base);
+        interpreter.set_current_class(class);
+        interpreter.set_call_depth(depth);
+    }
+    interpreter.vm().stack.replace(machine.stack);
+    result
+}
+
+fn token(frame: &Frame, index: u32) -> &Token {
+    &frame.closure.prototype.chunk.tokens[index as usize]
+}
+
+/// Runs frames until the one it started with returns. The stack is held
+/// here while running and handed back to the `Vm` whenever other code may
+/// call into the VM.
+struct Machine<'a> {
+    interpreter: &'a Interpreter,
+    stack: Vec<Object>,
+    frames: Vec<Frame>,
+}
+
+impl Machine<'_> {
+    fn run(&mut self, mut frame: Frame) -> Result<Object, Error> {
+        loop {
+            self.interpreter.step()?;
+            let op = frame.closure.prototype.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(index) => {
+                    let value = frame.closure.prototype.chunk.constants[index as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Nil => self.stack.push(Object::Nil),
+                Op::True => self.stack.push(Object::Bool(true)),
+                Op::False => self.stack.push(Object::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::GetLocal(slot) => {
+                    let value = self.stack[frame.base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::SetLocal(slot) => {
+                    self.stack[frame.base + slot as usize] = self.peek().clone();
+                }
+                Op::GetUpvalue(index) => {
+                    let value = match &*frame.closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Op::SetUpvalue(index) => {
+                    let value = self.peek().clone();
+                    match &mut *frame.closure.upvalues[index as usize].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                Op::DefineGlobal(name) => {
+                    let value = self.pop();
+                    self.interpreter
+                        .globals
+                        .borrow_mut()
+                        .define(token(&frame, name).lexeme.clone(), value);
+                }
+                Op::GetGlobal(name) => {
+                    let value = self.interpreter.globals.borrow().get(token(&frame, name))?;
+                    self.stack.push(value);
+                }
+                Op::SetGlobal(name) => {
+                    let value = self.peek().clone();
+                    self.interpreter
+                        .globals
+                        .borrow_mut()
+                        .assign(token(&frame, name), value)?;
+                }
+                Op::GetProperty(name) => {
+                    let object = self.pop();
+                    let value = self.outside(|terp| terp.get_property(object, token(&frame, name)))?;
+                    self.stack.push(value);
+                }
+                Op::SetProperty(name) => {
+                    let value = self.pop();
+                    let object = self.pop();
+                    self.outside(|terp| terp.set_property(object, token(&frame, name), value.clone()))?;
+                    self.stack.push(value);
+                }
+                Op::GetSuper(method) => {
+                    let superclass = self.pop();
+                    let this = self.pop();
+                    if let Object::Class(superclass) = superclass {
+                        let value = self.outside(|terp| terp.get_super(&superclass, &this, token(&frame, method)))?;
+                        self.stack.push(value);
+                    }
+                }
+                Op::GetIndex(bracket) => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = self.interpreter.index(token(&frame, bracket), object, index)?;
+                    self.stack.push(value);
+                }
+                Op::SetIndex(bracket) => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = self.interpreter.set_index(token(&frame, bracket), object, index, value)?;
+                    self.stack.push(value);
+                }
+                Op::Add(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Num(a + b)))?,
+                Op::Subtract(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Num(a - b)))?,
+                Op::Multiply(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Num(a * b)))?,
+                Op::Divide(operator) => {
+                    self.binary(token(&frame, operator), |a, b| (b != 0.0).then(|| Object::Num(a / b)))?
+                }
+                Op::Equal(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a == b)))?,
+                Op::NotEqual(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a != b)))?,
+                Op::Greater(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a > b)))?,
+                Op::GreaterEqual(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a >= b)))?,
+                Op::Less(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a < b)))?,
+                Op::LessEqual(operator) => self.binary(token(&frame, operator), |a, b| Some(Object::Bool(a <= b)))?,
+                Op::Not => {
+                    let value = self.pop();
+                    self.stack.push(Object::Bool(!self.interpreter.is_truthy(value)));
+                }
+                Op::Negate => {
+                    let value = match self.pop() {
+                        Object::Num(x) => Object::Num(-x),
+                        _ => Object::Nil,
+                    };
+                    self.stack.push(value);
+                }
+                Op::Print => {
+                    let value = self.pop();
+                    self.interpreter.write_output(&format!("{}\n", value))?;
+                }
+                Op::Jump(target) => frame.ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if !self.interpreter.is_truthy(self.peek().clone()) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::JumpIfTrue(target) => {
+                    if self.interpreter.is_truthy(self.peek().clone()) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::PopJumpIfFalse(target) => {
+                    let condition = self.pop();
+                    if !self.interpreter.is_truthy(condition) {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Call(count, paren) | Op::TailCall(count, paren) => {
+                    let count = count as usize;
+                    let callee = self.stack[self.stack.len() - 1 - count].clone();
+
+                    // Compiled functions run in this loop; everything else
+                    // goes through the interpreter.
+                    if let Object::Function(function) = &callee {
+                        if let Some((closure, this)) = function.bytecode() {
+                            if count != closure.prototype.params.len() {
+                                return Err(Error::runtime_error(
+                                    token(&frame, paren),
+                                    &format!(
+                                        "Expected {} arguments but got {}.",
+                                        closure.prototype.params.len(),
+                                        count
+                                    ),
+                                ));
+                            }
+                            let base = self.stack.len() - 1 - count;
+                            let this = this.cloned().unwrap_or(Object::Nil);
+                            let class = self.interpreter.set_current_class(function.owner());
+                            if let Op::TailCall(..) = op {
+                                // The caller has nothing left to do, so the
+                                // callee takes over its slots and its frame.
+                                self.close_upvalues(frame.base);
+                                self.stack.drain(frame.base..base);
+                                self.stack[frame.base] = this;
+                                frame.closure = Rc::clone(closure);
+                                frame.ip = 0;
+                            } else {
+                                self.interpreter
+                                    .enter_call()
+                                    .map_err(|e| call_site(e, token(&frame, paren)))?;
+                                self.stack[base] = this;
+                                let callee_frame = Frame {
+                                    closure: Rc::clone(closure),
+                                    ip: 0,
+                                    base,
+                                    class,
+                                };
+                                self.frames.push(std::mem::replace(&mut frame, callee_frame));
+                            }
+                            continue;
+                        }
+                    }
+
+                    let arguments = self.stack.split_off(self.stack.len() - count);
+                    self.pop();
+                    let value = self.outside(|terp| terp.call(&callee, arguments, Vec::new()));
+                    let value = value.map_err(|e| call_site(e, token(&frame, paren)))?;
+                    self.stack.push(value);
+                }
+                Op::CallKeywords(count, call) => {
+                    let (names, paren) = &frame.closure.prototype.chunk.keyword_calls[call as usize];
+                    let values = self.stack.split_off(self.stack.len() - names.len());
+                    let arguments = self.stack.split_off(self.stack.len() - count as usize);
+                    let callee = self.pop();
+                    let keywords = names.iter().zip(values).collect();
+                    let value = self.outside(|terp| terp.call(&callee, arguments, keywords));
+                    self.stack.push(value.map_err(|e| call_site(e, paren))?);
+                }
+                Op::Closure(index) => {
+                    let prototype = Rc::clone(&frame.closure.prototype.chunk.prototypes[index as usize]);
+                    let upvalues = prototype
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Local(slot) => self.capture(frame.base + slot as usize),
+                            Capture::Upvalue(index) => Rc::clone(&frame.closure.upvalues[index as usize]),
+                        })
+                        .collect();
+                    let closure = Rc::new(Closure { prototype, upvalues });
+                    // Without upvalues it can't be part of a cycle.
+                    if !closure.upvalues.is_empty() {
+                        gc::track(&closure);
+                    }
+                    let function = Function::compiled(closure, self.interpreter.current_class());
+                    self.stack.push(Object::Function(function));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Op::Return => {
+                    let value = self.pop();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    self.interpreter.set_current_class(frame.class);
+                    match self.frames.pop() {
+                        Some(caller) => {
+                            self.interpreter.exit_call();
+                            frame = caller;
+                            self.stack.push(value);
+                        }
+                        None => return Ok(value),
+                    }
+                }
+                Op::List(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(Object::list(elements));
+                }
+                Op::Map(count, brace) => {
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    let mut map = MapStruct::new();
+                    for entry in entries.chunks(2) {
+                        let key = self.interpreter.map_key(token(&frame, brace), &entry[0])?;
+                        map.insert(key.clone(), entry[1].clone());
+                    }
+                    self.stack.push(Object::map(map));
+                }
+                Op::Expect(kind, name) => {
+                    let message = match (kind, self.peek()) {
+                        (Kind::Class, Object::Class(_))
+                        | (Kind::Trait, Object::Trait(_))
+                        | (Kind::Interface, Object::Interface(_)) => None,
+                        (Kind::Class, _) => Some("Superclass must be a class."),
+                        (Kind::Trait, _) => Some("Can only mix in traits."),
+                        (Kind::Interface, _) => Some("Can only implement interfaces."),
+                    };
+                    if let Some(message) = message {
+                        return Err(Error::runtime_error(token(&frame, name), message));
+                    }
+                }
+                Op::Class(index) => {
+                    let layout = &frame.closure.prototype.chunk.layouts[index as usize];
+                    let class = self.class(layout)?;
+                    self.stack.push(Object::Class(class));
+                }
+                Op::Trait(index) => {
+                    let layout = &frame.closure.prototype.chunk.layouts[index as usize];
+                    let methods = self.pop_members(&layout.methods, None);
+                    let t = TraitStruct::new(layout.name.lexeme.to_string(), methods, layout.signatures.clone());
+                    self.stack.push(Object::Trait(t));
+                }
+                Op::Interface(index) => {
+                    let layout = &frame.closure.prototype.chunk.layouts[index as usize];
+                    let interface = InterfaceStruct::new(layout.name.lexeme.to_string(), layout.signatures.clone());
+                    self.stack.push(Object::Interface(Rc::new(interface)));
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    fn peek(&self) -> &Object {
+        self.stack.last().expect("VM stack underflow")
+    }
+
+    // Numbers take the fast path unless `numeric` declines, as division by
+    // zero does. Everything else is left to the interpreter's rules.
+    fn binary(&mut self, operator: &Token, numeric: fn(f64, f64) -> Option<Object>) -> Result<(), Error> {
+        let right = self.pop();
+        let left = self.pop();
+        let fast = match (&left, &right) {
+            (Object::Num(a), Object::Num(b)) => numeric(*a, *b),
+            _ => None,
+        };
+        let value = match fast {
+            Some(value) => value,
+            None => self.interpreter.binary(left, operator, right)?,
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    /// Runs code that may call back into the VM, such as natives, classes,
+    /// getters and setters, with the stack handed back to the `Vm`.
+    fn outside<T>(&mut self, f: impl FnOnce(&Interpreter) -> T) -> T {
+        let vm = self.interpreter.vm();
+        vm.stack.replace(std::mem::take(&mut self.stack));
+        let result = f(self.interpreter);
+        self.stack = vm.stack.take();
+        result
+    }
+
+    fn capture(&self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let mut open = self.interpreter.vm().open_upvalues.borrow_mut();
+        match open.binary_search_by_key(&slot, |(s, _)| *s) {
+            Ok(i) => Rc::clone(&open[i].1),
+            Err(i) => {
+                let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+                gc::track(&upvalue);
+                open.insert(i, (slot, Rc::clone(&upvalue)));
+                upvalue
+            }
+        }
+    }
+
+    /// Moves variables at or above `from` off the stack into their upvalues.
+    fn close_upvalues(&self, from: usize) {
+        let mut open = self.interpreter.vm().open_upvalues.borrow_mut();
+        while let Some((slot, upvalue)) = open.last() {
+            if *slot < from {
+                break;
+            }
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[*slot].clone());
+            open.pop();
+        }
+    }
+
+    fn class(&mut self, layout: &Layout) -> Result<Rc<ClassStruct>, Error> {
+        let id = self.interpreter.new_class_id();
+        let setters = self.pop_members(&layout.setters, Some(id));
+        let getters = self.pop_members(&layout.getters, Some(id));
+        let methods = self.pop_members(&layout.methods, Some(id));
+        let field_initializer = if layout.field_initializer {
+            match self.pop() {
+                Object::Function(function) => Some(function.with_owner(Some(id))),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        let interfaces = self.stack.split_off(self.stack.len() - layout.interfaces);
+        let traits = self.stack.split_off(self.stack.len() - layout.traits);
+        let superclass = if layout.superclass { Some(self.pop()) } else { None };
+
+        let members = ClassMembers {
+            methods,
+            getters,
+            setters,
+            abstract_methods: layout.signatures.clone(),
+            fields: layout.fields.clone(),
+            field_initializer,
+        };
+        self.interpreter.build_class(
+            &layout.name,
+            id,
+            superclass.and_then(|s| match s {
+                Object::Class(c) => Some(c),
+                _ => None,
+            }),
+            traits
+                .into_iter()
+                .filter_map(|t| match t {
+                    Object::Trait(t) => Some(t),
+                    _ => None,
+                })
+                .collect(),
+            interfaces
+                .into_iter()
+                .filter_map(|i| match i {
+                    Object::Interface(i) => Some(i),
+                    _ => None,
+                })
+                .collect(),
+            members,
+        )
+    }
+
+    // Methods are made before their class exists, so they are handed to
+    // the class they belong to here.
+    fn pop_members(&mut self, names: &[RcStr], owner: Option<usize>) -> HashMap<RcStr, Object> {
+        let functions = self.stack.split_off(self.stack.len() - names.len());
+        names
+            .iter()
+            .cloned()
+            .zip(functions.into_iter().map(|f| match f {
+                Object::Function(f) => Object::Function(f.with_owner(owner)),
+                other => other,
+            }))
+            .collect()
+    }
+}
+
+// Errors raised inside a call without a token of their own are reported at
+// the call's closing paren, as in the tree-walker.
+fn call_site(error: Error, paren: &Token) -> Error {
+    match error {
+        Error::CallError { message } => Error::runtime_error(paren, &message),
+        e => e,
+    }
+}
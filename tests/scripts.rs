@@ -0,0 +1,299 @@
+//! Runs the scripts under `tests/scripts` on both backends. A script says
+//! what it should do in comments, which can follow code on the same line:
+//!
+//!   ~ expect: <line>    a line it prints
+//!   ~ error: <line>     a line it reports on standard error
+//!   ~ exit: <code>      its exit code, when that isn't 0
+//!   ~ options: <opts>   interpreter options to run it with
+//!   ~ args: <args>      arguments passed to it
+//!   ~ stdin: <line>     a line of its standard input
+//!
+//! Each run happens in an empty directory of its own, so scripts can make
+//! files. The tree-walker and the VM must both do exactly what the comments
+//! say. The examples and the programs under `tests/parity` say nothing, and
+//! the two backends only have to agree on them.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const BACKENDS: [(&str, &[&str]); 2] = [("tree-walker", &[]), ("vm", &["--vm"])];
+
+#[derive(Default)]
+struct Expectations {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    exit: i32,
+    options: Vec<String>,
+    args: Vec<String>,
+    stdin: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: Vec<String>,
+    stderr: Vec<String>,
+    exit: i32,
+}
+
+fn expectations(source: &str) -> Expectations {
+    let mut expected = Expectations::default();
+    for line in source.lines() {
+        let Some(start) = line.find("~ ") else {
+            continue;
+        };
+        let comment = &line[start + 2..];
+        let Some((key, value)) = comment.split_once(':') else {
+            continue;
+        };
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        let words = || value.split_whitespace().map(String::from);
+        match key {
+            "expect" => expected.stdout.push(value.to_string()),
+            "error" => expected.stderr.push(value.to_string()),
+            "exit" => expected.exit = value.parse().expect("exit code"),
+            "options" => expected.options.extend(words()),
+            "args" => expected.args.extend(words()),
+            "stdin" => {
+                expected.stdin.push_str(value);
+                expected.stdin.push('\n');
+            }
+            _ => {}
+        }
+    }
+    expected
+}
+
+// A new, empty directory to run a script in.
+fn scratch_dir() -> PathBuf {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::SeqCst);
+    let dir = std::env::temp_dir().join(format!("arc-test-{}-{run}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("scratch directory");
+    dir
+}
+
+fn run(script: &Path, backend: &[&str], expected: &Expectations) -> Outcome {
+    let dir = scratch_dir();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_arc"))
+        .args(backend)
+        .args(&expected.options)
+        .arg(script)
+        .args(&expected.args)
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("arc binary");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(expected.stdin.as_bytes()).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+
+    let lines = |bytes: &[u8]| String::from_utf8_lossy(bytes).lines().map(String::from).collect();
+    Outcome {
+        stdout: lines(&output.stdout),
+        stderr: lines(&output.stderr),
+        exit: output.status.code().unwrap_or(-1),
+    }
+}
+
+// The scripts in a directory of the repository, in order.
+fn scripts(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut scripts: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("script directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "arc"))
+        .collect();
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts in {}", dir.display());
+    scripts
+}
+
+/// Runs every script in `tests/scripts/<suite>`, failing with a report of
+/// each one that didn't do what it says.
+fn check(suite: &str) {
+    let mut failures = Vec::new();
+    for script in &scripts(&format!("tests/scripts/{suite}")) {
+        let expected = expectations(&fs::read_to_string(script).unwrap());
+        for (name, backend) in BACKENDS {
+            let outcome = run(script, backend, &expected);
+            let mut problems = Vec::new();
+            if outcome.stdout != expected.stdout {
+                problems.push(format!("  stdout: {:?}\n  wanted: {:?}", outcome.stdout, expected.stdout));
+            }
+            if outcome.stderr != expected.stderr {
+                problems.push(format!("  stderr: {:?}\n  wanted: {:?}", outcome.stderr, expected.stderr));
+            }
+            if outcome.exit != expected.exit {
+                problems.push(format!("  exit: {}, wanted: {}", outcome.exit, expected.exit));
+            }
+            if !problems.is_empty() {
+                failures.push(format!("{} ({name}):\n{}", script.display(), problems.join("\n")));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n\n"));
+}
+
+/// Runs every script in `dir` on both backends, failing with a report of
+/// each one they don't print, report or exit the same on.
+fn compare(dir: &str) {
+    let mut failures = Vec::new();
+    for script in &scripts(dir) {
+        let expected = expectations(&fs::read_to_string(script).unwrap());
+        let [tree_walker, vm] = BACKENDS.map(|(_, backend)| run(script, backend, &expected));
+        if tree_walker != vm {
+            failures.push(format!(
+                "{}:\n  tree-walker: {tree_walker:?}\n  vm:          {vm:?}",
+                script.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}\n", failures.join("\n\n"));
+}
+
+#[test]
+fn getters_and_setters() {
+    check("getters_and_setters");
+}
+
+#[test]
+fn private_members() {
+    check("private_members");
+}
+
+#[test]
+fn traits() {
+    check("traits");
+}
+
+#[test]
+fn abstract_methods_and_interfaces() {
+    check("abstract_and_interfaces");
+}
+
+#[test]
+fn fields() {
+    check("fields");
+}
+
+#[test]
+fn reflection_and_lists() {
+    check("reflection");
+}
+
+#[test]
+fn strings() {
+    check("strings");
+}
+
+#[test]
+fn math() {
+    check("math");
+}
+
+#[test]
+fn files() {
+    check("files");
+}
+
+#[test]
+fn unreadable_script() {
+    let dir = scratch_dir();
+    let output = Command::new(env!("CARGO_BIN_EXE_arc"))
+        .arg("no-such-script.arc")
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(66));
+    assert!(stderr.starts_with("System Error: Could not read 'no-such-script.arc': "), "{stderr}");
+}
+
+#[test]
+fn input() {
+    check("input");
+}
+
+#[test]
+fn print() {
+    check("print");
+}
+
+#[test]
+fn process() {
+    check("process");
+}
+
+#[test]
+fn maps() {
+    check("maps");
+}
+
+#[test]
+fn json() {
+    check("json");
+}
+
+#[test]
+fn regex() {
+    check("regex");
+}
+
+#[test]
+fn datetime() {
+    check("datetime");
+}
+
+#[test]
+fn random() {
+    check("random");
+}
+
+#[test]
+fn collections() {
+    check("collections");
+}
+
+#[test]
+fn gc() {
+    check("gc");
+}
+
+#[test]
+fn backends_agree_on_examples() {
+    compare("examples");
+}
+
+#[test]
+fn backends_agree_on_programs() {
+    compare("tests/parity");
+}
+
+#[test]
+fn scopes() {
+    check("scopes");
+}
+
+#[test]
+fn tail_calls() {
+    check("tail_calls");
+}
+
+#[test]
+fn recursion() {
+    check("recursion");
+}
+
+#[test]
+fn limits() {
+    check("limits");
+}